@@ -1,5 +1,11 @@
 use std::fmt;
 use std::fmt::Formatter;
+use axum::extract::rejection::QueryRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use log::error;
+use serde::Serialize;
 use log4rs::config::runtime::ConfigErrors;
 use log::SetLoggerError;
 use crate::manager_db::errors::DBError;
@@ -27,9 +33,16 @@ impl From<ConfigError> for UnrecoverableError {
 impl From<DBError> for UnrecoverableError {
     fn from(e: DBError) -> Self { UnrecoverableError(e.to_string()) }
 }
+impl From<BackupError> for UnrecoverableError {
+    fn from(e: BackupError) -> Self { UnrecoverableError(e.to_string()) }
+}
+impl From<ImportError> for UnrecoverableError {
+    fn from(e: ImportError) -> Self { UnrecoverableError(e.to_string()) }
+}
 
 /// Errors while managing configuration
 ///
+#[derive(Debug)]
 pub struct ConfigError(pub String);
 
 impl fmt::Display for ConfigError {
@@ -69,3 +82,187 @@ impl From<reqwest::Error> for TempError {
 impl From<serde_json::Error> for TempError {
     fn from(e: serde_json::Error) -> Self { TempError(e.to_string()) }
 }
+
+pub struct BackupError(pub String);
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "BackupError: {}", self.0) }
+}
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self { BackupError(e.to_string()) }
+}
+impl From<DBError> for BackupError {
+    fn from(e: DBError) -> Self { BackupError(e.to_string()) }
+}
+
+#[derive(Debug)]
+pub struct ImportError(pub String);
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "ImportError: {}", self.0) }
+}
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self { ImportError(e.to_string()) }
+}
+impl From<csv::Error> for ImportError {
+    fn from(e: csv::Error) -> Self { ImportError(e.to_string()) }
+}
+impl From<DBError> for ImportError {
+    fn from(e: DBError) -> Self { ImportError(e.to_string()) }
+}
+impl From<&str> for ImportError {
+    fn from(e: &str) -> Self { ImportError(e.to_string()) }
+}
+
+#[derive(Debug)]
+pub struct InfluxError(pub String);
+impl fmt::Display for InfluxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "InfluxError: {}", self.0) }
+}
+impl From<std::io::Error> for InfluxError {
+    fn from(e: std::io::Error) -> Self { InfluxError(e.to_string()) }
+}
+impl From<reqwest::Error> for InfluxError {
+    fn from(e: reqwest::Error) -> Self { InfluxError(e.to_string()) }
+}
+
+/// Errors returned by the web api, rendered as a json body with an error code and a message
+///
+#[derive(Debug)]
+pub enum ApiError {
+    /// A query parameter is missing or malformed
+    InvalidParameter(String),
+    /// Nothing is configured or stored for the requested source
+    UnknownSource(String),
+    /// A feature needed by the request isn't configured
+    NotConfigured(String),
+    /// None of the formats in the Accept header is supported
+    NotAcceptable(String),
+    /// Missing or wrong admin token
+    Unauthorized,
+    /// Admin endpoints are disabled
+    Forbidden(String),
+    /// The storage backend doesn't support the request
+    NotImplemented(String),
+    /// The database is too busy to answer in time, the client may retry later
+    Unavailable(String),
+    /// Anything else, details are logged but not returned to the client
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl ApiError {
+    /// Returns the http status and error code of the error
+    ///
+    fn status_and_code(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::InvalidParameter(_) => (StatusCode::BAD_REQUEST, "invalid_parameter"),
+            ApiError::UnknownSource(_) => (StatusCode::NOT_FOUND, "unknown_source"),
+            ApiError::NotConfigured(_) => (StatusCode::NOT_FOUND, "not_configured"),
+            ApiError::NotAcceptable(_) => (StatusCode::NOT_ACCEPTABLE, "not_acceptable"),
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden"),
+            ApiError::NotImplemented(_) => (StatusCode::NOT_IMPLEMENTED, "not_implemented"),
+            ApiError::Unavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+            ApiError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+        }
+    }
+
+    /// Returns the error code, e.g. 'invalid_parameter'
+    ///
+    pub fn code(&self) -> &'static str {
+        self.status_and_code().1
+    }
+
+    /// Returns the message given to the client, internal errors are logged instead
+    ///
+    pub fn into_message(self) -> String {
+        match self {
+            ApiError::Internal(e) => {
+                error!("internal error: {}", e);
+                "internal error".to_string()
+            },
+            ApiError::UnknownSource(source) => format!("unknown source '{}'", source),
+            ApiError::Unauthorized => "missing or invalid admin token".to_string(),
+            ApiError::InvalidParameter(m) | ApiError::NotConfigured(m) | ApiError::NotAcceptable(m) | ApiError::Forbidden(m)
+            | ApiError::NotImplemented(m) | ApiError::Unavailable(m) => m,
+        }
+    }
+}
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidParameter(m) | ApiError::NotConfigured(m) | ApiError::NotAcceptable(m) | ApiError::Forbidden(m)
+            | ApiError::NotImplemented(m) | ApiError::Unavailable(m) | ApiError::Internal(m) => write!(f, "ApiError: {}", m),
+            ApiError::UnknownSource(source) => write!(f, "ApiError: unknown source '{}'", source),
+            ApiError::Unauthorized => write!(f, "ApiError: missing or invalid admin token"),
+        }
+    }
+}
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code) = self.status_and_code();
+        let message = self.into_message();
+
+        (status, Json(ApiErrorBody { code, message })).into_response()
+    }
+}
+impl From<DBError> for ApiError {
+    fn from(e: DBError) -> Self {
+        match e {
+            DBError::PoolTimeout => ApiError::Unavailable("database is busy, try again later".to_string()),
+            e => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+impl From<BackupError> for ApiError {
+    fn from(e: BackupError) -> Self { ApiError::Internal(e.to_string()) }
+}
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self { ApiError::InvalidParameter(e.body_text()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the status and json body of the response to the error
+    async fn response(e: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = e.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn responds_with_status_code_and_message() {
+        let cases = [
+            (ApiError::InvalidParameter("invalid step".to_string()), 400, "invalid_parameter", "invalid step"),
+            (ApiError::UnknownSource("cabin".to_string()), 404, "unknown_source", "unknown source 'cabin'"),
+            (ApiError::NotConfigured("no backups".to_string()), 404, "not_configured", "no backups"),
+            (ApiError::NotAcceptable("use json".to_string()), 406, "not_acceptable", "use json"),
+            (ApiError::Unauthorized, 401, "unauthorized", "missing or invalid admin token"),
+            (ApiError::Forbidden("admin disabled".to_string()), 403, "forbidden", "admin disabled"),
+            (ApiError::NotImplemented("no export".to_string()), 501, "not_implemented", "no export"),
+            (ApiError::Unavailable("busy".to_string()), 503, "unavailable", "busy"),
+            (ApiError::Internal("disk full at /var/lib".to_string()), 500, "internal_error", "internal error"),
+        ];
+
+        for (e, status, code, message) in cases {
+            assert_eq!(e.code(), code);
+            assert_eq!(response(e).await, (StatusCode::from_u16(status).unwrap(), serde_json::json!({"code": code, "message": message})));
+        }
+    }
+
+    #[test]
+    fn only_pool_timeouts_are_unavailable() {
+        assert!(matches!(ApiError::from(DBError::PoolTimeout), ApiError::Unavailable(_)));
+
+        let same_message = DBError::Other(DBError::PoolTimeout.message().to_string());
+        assert!(matches!(ApiError::from(same_message), ApiError::Internal(_)));
+    }
+}
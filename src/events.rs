@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use crate::manager_db::models::Observation;
+use crate::units::{Convert, Units};
+
+/// Number of events kept for subscribers that fall behind, older events are dropped for them
+const EVENT_CAPACITY: usize = 256;
+
+/// Sender of events to everyone subscribed, e.g. the /stream clients
+pub type Events = broadcast::Sender<Event>;
+
+/// Kinds of events, used to filter subscriptions
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Observation,
+    Forecast,
+}
+
+/// A forecast refreshed from its provider
+#[derive(Serialize, Clone, Debug)]
+pub struct ForecastRefresh {
+    pub source: String,
+    pub date_time: DateTime<Utc>,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub count: usize,
+}
+
+/// Something new that was stored
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Event {
+    Observation(Observation),
+    Forecast(ForecastRefresh),
+}
+
+impl Event {
+    /// Returns the kind of the event
+    ///
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Observation(_) => EventKind::Observation,
+            Event::Forecast(_) => EventKind::Forecast,
+        }
+    }
+
+    /// Returns the name of the event kind, as given in requests and in the event stream
+    ///
+    pub fn name(&self) -> &'static str {
+        match self.kind() {
+            EventKind::Observation => "observation",
+            EventKind::Forecast => "forecast",
+        }
+    }
+
+    /// Returns the source the event is about
+    ///
+    pub fn source(&self) -> &str {
+        match self {
+            Event::Observation(observation) => &observation.source,
+            Event::Forecast(refresh) => &refresh.source,
+        }
+    }
+}
+
+impl Convert for Event {
+    fn convert(&mut self, units: &Units) {
+        if let Event::Observation(observation) = self {
+            observation.convert(units);
+        }
+    }
+}
+
+/// Which events a subscriber wants, everything if a filter is None
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub sources: Option<Vec<String>>,
+    pub kinds: Option<Vec<EventKind>>,
+}
+
+impl EventFilter {
+    /// Creates a filter from comma separated lists of sources and event kinds
+    ///
+    /// # Arguments
+    ///
+    /// * 'sources' - sources, e.g. 'east_west,smhi'
+    /// * 'kinds' - event kinds, e.g. 'observation'
+    pub fn from_params(sources: Option<&str>, kinds: Option<&str>) -> Result<Self, String> {
+        let sources = sources.map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
+        let kinds = kinds
+            .map(|kinds| kinds.split(',')
+                .map(|kind| match kind.trim() {
+                    "observation" => Ok(EventKind::Observation),
+                    "forecast" => Ok(EventKind::Forecast),
+                    kind => Err(format!("invalid event: {}", kind)),
+                })
+                .collect::<Result<Vec<EventKind>, String>>())
+            .transpose()?;
+
+        Ok(EventFilter { sources, kinds })
+    }
+
+    /// Returns true if the event passes the filter
+    ///
+    /// # Arguments
+    ///
+    /// * 'event' - the event
+    pub fn matches(&self, event: &Event) -> bool {
+        self.sources.as_ref().is_none_or(|sources| sources.iter().any(|s| s == event.source()))
+            && self.kinds.as_ref().is_none_or(|kinds| kinds.contains(&event.kind()))
+    }
+}
+
+/// Creates the channel events are sent over
+///
+pub fn channel() -> Events {
+    broadcast::channel(EVENT_CAPACITY).0
+}
+
+/// Sends an event to everyone subscribed, if anyone
+///
+/// # Arguments
+///
+/// * 'events' - sender of events
+/// * 'event' - the event
+pub fn publish(events: &Events, event: Event) {
+    // Sending only fails when no one is subscribed
+    let _ = events.send(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(source: &str) -> Event {
+        Event::Observation(Observation {
+            source: source.to_string(),
+            date_time: DateTime::from_timestamp(0, 0).unwrap(),
+            temperature: Some(4.5),
+            humidity: None,
+            perceived_temperature: None,
+        })
+    }
+
+    fn forecast(source: &str) -> Event {
+        Event::Forecast(ForecastRefresh {
+            source: source.to_string(),
+            date_time: DateTime::from_timestamp(0, 0).unwrap(),
+            first: None,
+            last: None,
+            count: 0,
+        })
+    }
+
+    #[test]
+    fn passes_everything_without_filters() {
+        let filter = EventFilter::from_params(None, None).unwrap();
+
+        assert!(filter.matches(&observation("east_west")));
+        assert!(filter.matches(&forecast("smhi")));
+    }
+
+    #[test]
+    fn filters_on_sources_and_kinds() {
+        let filter = EventFilter::from_params(Some("east_west, smhi"), None).unwrap();
+        assert!(filter.matches(&observation("east_west")));
+        assert!(filter.matches(&forecast("smhi")));
+        assert!(!filter.matches(&observation("cabin")));
+
+        let filter = EventFilter::from_params(None, Some("forecast")).unwrap();
+        assert!(filter.matches(&forecast("smhi")));
+        assert!(!filter.matches(&observation("east_west")));
+
+        let filter = EventFilter::from_params(Some("east_west"), Some("observation, forecast")).unwrap();
+        assert!(filter.matches(&observation("east_west")));
+        assert!(!filter.matches(&forecast("smhi")));
+    }
+
+    #[test]
+    fn rejects_unknown_kinds() {
+        assert_eq!(EventFilter::from_params(None, Some("observation,rain")).unwrap_err(), "invalid event: rain");
+    }
+}
@@ -1,63 +1,720 @@
+use std::convert::Infallible;
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+use axum::body::{Body, Bytes};
+use axum::extract::rejection::QueryRejection;
 use axum::extract::{Query, State};
-use axum::http::{header, StatusCode};
-use axum::response::IntoResponse;
-use log::{error, info};
-use serde::Deserialize;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use crate::errors::ApiError;
+use crate::events::EventFilter;
+use crate::manager_backup::backup_now;
+use crate::initialization::{Config, MAX_FORECAST_DAYS};
+use crate::local_time::{day_range, named_range, start_of_day, CalendarPeriod};
+use crate::manager_db::export::Export;
+use crate::manager_db::models::{
+    BucketedTemperature, CurrentConditions, DailyForecast, Forecast, ForecastRecord, Location, MinMax, MinMaxPeriod, Source, SourceKind,
+    SourceStats, Temperature,
+};
+use crate::manager_db::rollups::Resolution;
+use crate::negotiation::{respond, with_units, ContentType, ToCsv};
+use crate::resample::{parse_step, Resampling, MAX_GRID_POINTS, MAX_STEP_DAYS};
+use crate::statistics::{Stat, MAX_BUCKETS};
+use crate::units::{Convert, UnitSystem, Units};
 use crate::SharedState;
 
 
 #[derive(Deserialize, Debug)]
 pub struct TempParams {
     id: String,
-    from: String,
-    to: String,
+    from: Option<String>,
+    to: Option<String>,
+    date: Option<String>,
+    range: Option<String>,
+    resolution: Option<Resolution>,
+    bucket: Option<String>,
+    stats: Option<String>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForecastParams {
+    id: String,
+    from: Option<String>,
+    to: Option<String>,
+    date: Option<String>,
+    range: Option<String>,
+    symbols: Option<bool>,
+    step: Option<String>,
+    agg: Option<String>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForecastAtParams {
+    id: String,
+    time: String,
+    symbols: Option<bool>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DailyForecastParams {
+    id: String,
+    from: Option<String>,
+    to: Option<String>,
+    date: Option<String>,
+    range: Option<String>,
+    symbols: Option<bool>,
+    units: Option<UnitSystem>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct MinMaxParams {
+    id: String,
+    from: Option<String>,
+    to: Option<String>,
+    date: Option<String>,
+    range: Option<String>,
+    per: Option<CalendarPeriod>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CurrentParams {
+    id: Option<String>,
+    symbols: Option<bool>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamParams {
+    id: Option<String>,
+    events: Option<String>,
+    units: Option<UnitSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportParams {
     id: String,
     from: String,
     to: String,
+    data: Option<String>,
+    columns: Option<String>,
+    format: Option<String>,
 }
 
-pub async fn temperature(Query(params): Query<TempParams>, State(state): State<SharedState>) -> impl IntoResponse {
-    info!("temperature: {:?}", params);
+/// Max number of calendar periods of one min/max request
+const MAX_MIN_MAX_PERIODS: i64 = 1000;
+
+/// Number of days of the daily forecast of sources that aren't configured, unless otherwise requested
+const DAILY_FORECAST_DAYS: i64 = 10;
+
+/// Interval of the keep-alive comments of the event stream
+const KEEP_ALIVE_SECS: u64 = 15;
+
+/// Size of the chunks an export is streamed in
+const EXPORT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Time an export waits for the client to read the next chunk before giving up on it
+const EXPORT_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Writer sending what is written in chunks over a channel, used to stream exports made on the blocking
+/// thread pool to the response body. Since the channel is bounded the export only proceeds as fast as
+/// the client reads, and is abandoned if the client stops reading for `EXPORT_WRITE_TIMEOUT`.
+struct ChannelWriter {
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= EXPORT_CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let chunk = Bytes::from(std::mem::take(&mut self.buf));
+            Handle::current().block_on(self.tx.send_timeout(Ok(chunk), EXPORT_WRITE_TIMEOUT))
+                .map_err(|e| match e {
+                    SendTimeoutError::Timeout(_) => io::Error::new(io::ErrorKind::TimedOut, "export client stopped reading"),
+                    SendTimeoutError::Closed(_) => io::Error::new(io::ErrorKind::BrokenPipe, "export client disconnected"),
+                })?;
+        }
+
+        Ok(())
+    }
+}
 
-    let db = state.lock().await;
+/// A range of time, the end is not inclusive
+type TimeRange = (DateTime<Utc>, DateTime<Utc>);
 
-    match db.get_temp_history(&params.id, &params.from, &params.to) {
-        Ok(json) => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
-        Err(e) => {
-            error!("failed to get temp history: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+/// Parses a datetime parameter given in the rfc3339 format, or as a date meaning the start of that local day
+///
+/// # Arguments
+///
+/// * 'name' - name of the parameter
+/// * 'value' - value of the parameter
+/// * 'tz' - time zone of local days
+fn parse_time(name: &str, value: &str, tz: Tz) -> Result<DateTime<Utc>, ApiError> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(start_of_day(date, tz));
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| ApiError::InvalidParameter(format!("'{}' is neither an rfc3339 datetime nor a date: {}", name, e)))
+}
+
+/// Returns the requested range, given either by 'from' and 'to', a local 'date' or a named 'range'
+/// (see `named_range`), or None if none of them are given
+///
+/// # Arguments
+///
+/// * 'from' - value of the 'from' parameter
+/// * 'to' - value of the 'to' parameter
+/// * 'date' - value of the 'date' parameter
+/// * 'range' - value of the 'range' parameter
+/// * 'tz' - time zone of local days
+fn parse_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    date: Option<&str>,
+    range: Option<&str>,
+    tz: Tz,
+) -> Result<Option<TimeRange>, ApiError> {
+    let (from, to) = match (from, to, date, range) {
+        (None, None, None, None) => return Ok(None),
+        (Some(from), Some(to), None, None) => (parse_time("from", from, tz)?, parse_time("to", to, tz)?),
+        (None, None, Some(date), None) => {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|e| ApiError::InvalidParameter(format!("'date' is not a date: {}", e)))?;
+            day_range(date, tz)
+        },
+        (None, None, None, Some(range)) => named_range(range, Utc::now(), tz)
+            .ok_or(ApiError::InvalidParameter(format!("invalid range: {}, use today, yesterday, tomorrow or last<N>d", range)))?,
+        _ => return Err(ApiError::InvalidParameter("give either both 'from' and 'to', 'date' or 'range'".to_string())),
+    };
+
+    if from >= to {
+        return Err(ApiError::InvalidParameter("'from' must be before 'to'".to_string()));
+    }
+
+    Ok(Some((from, to)))
+}
+
+/// Returns the requested range, see `parse_range`, which must be given
+///
+/// # Arguments
+///
+/// * 'from' - value of the 'from' parameter
+/// * 'to' - value of the 'to' parameter
+/// * 'date' - value of the 'date' parameter
+/// * 'range' - value of the 'range' parameter
+/// * 'tz' - time zone of local days
+fn required_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    date: Option<&str>,
+    range: Option<&str>,
+    tz: Tz,
+) -> Result<TimeRange, ApiError> {
+    parse_range(from, to, date, range, tz)?
+        .ok_or(ApiError::InvalidParameter("missing range, give either 'from' and 'to', 'date' or 'range'".to_string()))
+}
+
+/// Checks that the source is either configured or has something stored in the database
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'source' - the requested source
+pub async fn check_source(state: &SharedState, source: &str) -> Result<(), ApiError> {
+    let configured = state.config.temperature.name == source || state.config.forecast(source).is_some();
+
+    if configured || state.db.has_source(source).await? {
+        Ok(())
+    } else {
+        Err(ApiError::UnknownSource(source.to_string()))
+    }
+}
+
+/// Result of a query, answered in the negotiated format over http or as json over the websocket
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Temperature(Temperature),
+    BucketedTemperature(BucketedTemperature),
+    MinMax(Option<MinMax>),
+    MinMaxSeries(Vec<MinMaxPeriod>),
+    Forecast(Forecast),
+    ForecastAt(Option<ForecastRecord>),
+    DailyForecast(Vec<DailyForecast>),
+    Current(Vec<CurrentConditions>),
+}
+
+impl ToCsv for Reply {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        match self {
+            Reply::Temperature(t) => t.to_csv(writer),
+            Reply::BucketedTemperature(t) => t.to_csv(writer),
+            Reply::MinMax(m) => m.to_csv(writer),
+            Reply::MinMaxSeries(m) => m.to_csv(writer),
+            Reply::Forecast(f) => f.to_csv(writer),
+            Reply::ForecastAt(f) => f.to_csv(writer),
+            Reply::DailyForecast(f) => f.to_csv(writer),
+            Reply::Current(c) => c.to_csv(writer),
         }
     }
 }
 
-pub async fn min_max(Query(params): Query<MinMaxParams>, State(state): State<SharedState>) -> impl IntoResponse {
+/// Returns the value converted to the given units
+///
+/// # Arguments
+///
+/// * 'value' - value in the stored units
+/// * 'units' - units to convert to
+fn converted<T: Convert>(mut value: T, units: &Units) -> T {
+    value.convert(units);
+
+    value
+}
+
+pub async fn temperature(headers: HeaderMap, params: Result<Query<TempParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("temperature: {:?}", params);
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_temperature(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
+
+/// Returns the temperature history of a range, or statistics of it if a bucket or statistics are requested
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_temperature(state: &SharedState, params: TempParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    let (from, to) = required_range(
+        params.from.as_deref(), params.to.as_deref(), params.date.as_deref(), params.range.as_deref(), state.config.general.time_zone,
+    )?;
+
+    if params.bucket.is_none() && params.stats.is_none() {
+        check_source(state, &params.id).await?;
+        let result = state.db.get_temp_history(&params.id, from, to, &state.config.temperature.forecast, params.resolution).await?;
+
+        return Ok((Reply::Temperature(converted(result, &units)), units));
+    }
+
+    let bucket = params.bucket.as_deref()
+        .map(|b| parse_step(b).ok_or_else(|| {
+            ApiError::InvalidParameter(format!("invalid bucket: {}, use e.g. 1h or 1d up to {}d", b, MAX_STEP_DAYS))
+        }))
+        .transpose()?;
+    if bucket.is_some_and(|b| (to - from).num_seconds() / b.num_seconds() > MAX_BUCKETS) {
+        return Err(ApiError::InvalidParameter(format!("more than {} buckets requested", MAX_BUCKETS)));
+    }
+    let stats = match params.stats.as_deref() {
+        Some(stats) => Stat::parse_list(stats).map_err(ApiError::InvalidParameter)?,
+        None => vec![Stat::Mean],
+    };
+
+    check_source(state, &params.id).await?;
+    let result = state.db.get_temp_stats(
+        &params.id, from, to, &state.config.temperature.forecast, bucket, state.config.general.time_zone, stats, Utc::now(),
+    ).await?;
+
+    Ok((Reply::BucketedTemperature(converted(result, &units)), units))
+}
+
+pub async fn min_max(headers: HeaderMap, params: Result<Query<MinMaxParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
     info!("minmax: {:?}", params);
 
-    let db = state.lock().await;
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_min_max(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
 
-    match db.get_min_max(&params.id, &params.from, &params.to) {
-        Ok(json) => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
-        Err(e) => {
-            error!("failed to get min/max: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+/// Returns the min/max temperature of a range, or of each calendar period within it if requested
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_min_max(state: &SharedState, params: MinMaxParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    let (from, to) = required_range(
+        params.from.as_deref(), params.to.as_deref(), params.date.as_deref(), params.range.as_deref(), state.config.general.time_zone,
+    )?;
+    if let Some(per) = params.per {
+        if (to - from).num_days() / per.min_days() > MAX_MIN_MAX_PERIODS {
+            return Err(ApiError::InvalidParameter(format!("more than {} periods requested", MAX_MIN_MAX_PERIODS)));
         }
     }
+
+    check_source(state, &params.id).await?;
+
+    let reply = match params.per {
+        Some(per) => Reply::MinMaxSeries(converted(
+            state.db.get_min_max_series(&params.id, from, to, per, Utc::now(), state.config.general.time_zone).await?,
+            &units,
+        )),
+        None => Reply::MinMax(converted(state.db.get_min_max(&params.id, from, to).await?, &units)),
+    };
+
+    Ok((reply, units))
 }
 
-pub async fn forecast(Query(params): Query<TempParams>, State(state): State<SharedState>) -> impl IntoResponse {
+pub async fn forecast(headers: HeaderMap, params: Result<Query<ForecastParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
     info!("forecast: {:?}", params);
-    
-    let db = state.lock().await;
-    
-    match db.get_forecast(&params.id, &params.from, &params.to) {
-        Ok(json) => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
-        Err(e) => {
-            error!("failed to get forecast: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_forecast(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
+
+/// Returns the forecast of a range, optionally resampled
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_forecast(state: &SharedState, params: ForecastParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    let (from, to) = required_range(
+        params.from.as_deref(), params.to.as_deref(), params.date.as_deref(), params.range.as_deref(), state.config.general.time_zone,
+    )?;
+
+    let resampling = params.step.as_deref()
+        .map(|step| Resampling::from_params(step, params.agg.as_deref()))
+        .transpose()
+        .map_err(ApiError::InvalidParameter)?;
+    if resampling.is_some_and(|r| (to - from).num_seconds() / r.step().num_seconds() > MAX_GRID_POINTS) {
+        return Err(ApiError::InvalidParameter(format!("more than {} grid points requested", MAX_GRID_POINTS)));
+    }
+
+    check_source(state, &params.id).await?;
+
+    let location = if params.symbols.unwrap_or(false) {
+        state.config.forecast(&params.id).map(|fc| (fc.lat, fc.long))
+    } else {
+        None
+    };
+
+    let result = state.db.get_forecast(&params.id, from, to, location, resampling).await?;
+
+    Ok((Reply::Forecast(converted(result, &units)), units))
+}
+
+pub async fn forecast_at(headers: HeaderMap, params: Result<Query<ForecastAtParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("forecast at: {:?}", params);
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_forecast_at(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
+
+/// Returns the forecast at a point in time
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_forecast_at(state: &SharedState, params: ForecastAtParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    let time = parse_time("time", &params.time, state.config.general.time_zone)?;
+    check_source(state, &params.id).await?;
+
+    let location = if params.symbols.unwrap_or(false) {
+        state.config.forecast(&params.id).map(|fc| (fc.lat, fc.long))
+    } else {
+        None
+    };
+
+    let result = state.db.get_forecast_at(&params.id, time, location).await?;
+
+    Ok((Reply::ForecastAt(converted(result, &units)), units))
+}
+
+pub async fn daily_forecast(headers: HeaderMap, params: Result<Query<DailyForecastParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("daily forecast: {:?}", params);
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_daily_forecast(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
+
+/// Returns the forecast summarized per local calendar day
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_daily_forecast(state: &SharedState, params: DailyForecastParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    let tz = state.config.general.time_zone;
+    let (from, to) = match parse_range(params.from.as_deref(), params.to.as_deref(), params.date.as_deref(), params.range.as_deref(), tz) {
+        Ok(range) => range.unzip(),
+        // Either boundary may be left out, defaulting to today and the following days
+        Err(_) if params.date.is_none() && params.range.is_none() => (
+            params.from.as_deref().map(|from| parse_time("from", from, tz)).transpose()?,
+            params.to.as_deref().map(|to| parse_time("to", to, tz)).transpose()?,
+        ),
+        Err(e) => return Err(e),
+    };
+    if from.zip(to).is_some_and(|(from, to)| from >= to) {
+        return Err(ApiError::InvalidParameter("'from' must be before 'to'".to_string()));
+    }
+    if to.is_some_and(|to| (to - from.unwrap_or_else(Utc::now)).num_days() > MAX_FORECAST_DAYS) {
+        return Err(ApiError::InvalidParameter(format!("more than {} days requested", MAX_FORECAST_DAYS)));
+    }
+
+    check_source(state, &params.id).await?;
+
+    let location = if params.symbols.unwrap_or(false) {
+        state.config.forecast(&params.id).map(|fc| (fc.lat, fc.long))
+    } else {
+        None
+    };
+
+    // By default the days fetched from the provider
+    let days = state.config.forecast(&params.id).map_or(DAILY_FORECAST_DAYS, |fc| fc.days);
+
+    let result = state.db.get_daily_forecast(&params.id, from, to, days, location, tz).await?;
+
+    Ok((Reply::DailyForecast(converted(result, &units)), units))
+}
+
+pub async fn current(headers: HeaderMap, params: Result<Query<CurrentParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("current: {:?}", params);
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let (reply, units) = query_current(&state, params).await?;
+
+    respond(content_type, &reply, Some(&units))
+}
+
+/// Returns the current conditions of each observation source, or of the requested one
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'params' - query parameters
+pub async fn query_current(state: &SharedState, params: CurrentParams) -> Result<(Reply, Units), ApiError> {
+    let units = state.config.units(params.units);
+    if let Some(id) = params.id.as_deref() {
+        check_source(state, id).await?;
+    }
+
+    let forecast_source = &state.config.temperature.forecast;
+    let location = if params.symbols.unwrap_or(false) {
+        state.config.forecast(forecast_source).map(|fc| (fc.lat, fc.long))
+    } else {
+        None
+    };
+
+    let result = state.db.get_current(params.id.as_deref(), forecast_source, location, Utc::now(), state.config.general.time_zone).await?;
+
+    Ok((Reply::Current(converted(result, &units)), units))
+}
+
+pub async fn sources(headers: HeaderMap, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    info!("sources");
+
+    let content_type = ContentType::negotiate(&headers)?;
+    let stats = state.db.source_stats().await?;
+
+    respond(content_type, &known_sources(&state.config, stats), None)
+}
+
+pub async fn stream(params: Result<Query<StreamParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("stream: {:?}", params);
+
+    let filter = EventFilter::from_params(params.id.as_deref(), params.events.as_deref())
+        .map_err(ApiError::InvalidParameter)?;
+    for source in filter.sources.iter().flatten() {
+        check_source(&state, source).await?;
+    }
+    let units = state.config.units(params.units);
+
+    let events = BroadcastStream::new(state.events.subscribe())
+        .filter_map(move |event| match event {
+            Ok(mut event) if filter.matches(&event) => {
+                event.convert(&units);
+                match with_units(&event, &units) {
+                    Ok(data) => Some(Ok::<SseEvent, Infallible>(SseEvent::default().event(event.name()).data(data.to_string()))),
+                    Err(e) => {
+                        error!("failed to serialize event: {}", e);
+                        None
+                    },
+                }
+            },
+            Ok(_) => None,
+            Err(BroadcastStreamRecvError::Lagged(count)) => {
+                warn!("stream client fell behind, {} events dropped", count);
+                None
+            },
+        });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_secs(KEEP_ALIVE_SECS))).into_response())
+}
+
+/// Returns every configured source together with every source found in the database, ordered by id and kind.
+/// An observation source is located at its forecast location.
+///
+/// # Arguments
+///
+/// * 'config' - application configuration
+/// * 'stats' - what is stored for each source in the database
+fn known_sources(config: &Config, stats: Vec<SourceStats>) -> Vec<Source> {
+    let location = |name: &str| config.forecast(name).map(|fc| Location { lat: fc.lat, long: fc.long });
+
+    let mut configured = vec![(
+        config.temperature.name.as_str(),
+        SourceKind::Observation,
+        config.temperature.display_name.clone(),
+        location(&config.temperature.forecast),
+    )];
+    for fc in &config.weather_forecast {
+        configured.push((fc.name.as_str(), SourceKind::Forecast, fc.display_name.clone(), location(&fc.name)));
+    }
+
+    let mut result = stats.into_iter()
+        .map(|s| {
+            let config = configured.iter().find(|(id, kind, _, _)| *id == s.source && *kind == s.kind);
+
+            Source {
+                configured: config.is_some(),
+                name: config.and_then(|c| c.2.clone()),
+                location: config.and_then(|c| c.3),
+                id: s.source,
+                kind: s.kind,
+                first: s.first,
+                last: s.last,
+                count: s.count,
+            }
+        })
+        .collect::<Vec<Source>>();
+
+    for (id, kind, name, location) in configured {
+        if !result.iter().any(|s| s.id == id && s.kind == kind) {
+            result.push(Source {
+                id: id.to_string(),
+                kind,
+                name,
+                configured: true,
+                location,
+                first: None,
+                last: None,
+                count: 0,
+            });
         }
     }
-}
\ No newline at end of file
+    result.sort_by(|a, b| (a.id.as_str(), a.kind as u8).cmp(&(b.id.as_str(), b.kind as u8)));
+
+    result
+}
+
+pub async fn export(params: Result<Query<ExportParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("export: {:?}", params);
+
+    let export_request = Export::new(
+        params.data.as_deref().unwrap_or("observation"),
+        &params.id,
+        &params.from,
+        &params.to,
+        params.columns.as_deref(),
+        params.format.as_deref().unwrap_or("csv"),
+    ).map_err(|e| ApiError::InvalidParameter(e.message().to_string()))?;
+
+    if !state.db.supports_export() {
+        return Err(ApiError::NotImplemented("export is not supported by this storage backend".to_string()));
+    }
+    check_source(&state, &params.id).await?;
+
+    let headers = [
+        (header::CONTENT_TYPE, export_request.format.content_type().to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", export_request.file_name())),
+    ];
+
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        let writer = ChannelWriter { tx: tx.clone(), buf: Vec::with_capacity(EXPORT_CHUNK_SIZE) };
+        match state.db.export(export_request, Box::new(writer)).await {
+            Ok(count) => info!("exported {} rows", count),
+            Err(e) => {
+                error!("failed to export: {}", e);
+                let _ = tx.send(Err(io::Error::other(e.message().to_string()))).await;
+            }
+        }
+    });
+
+    Ok((headers, Body::from_stream(ReceiverStream::new(rx))).into_response())
+}
+
+pub async fn admin_backup(headers: HeaderMap, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    info!("admin backup");
+
+    let Some(token) = state.config.web_server.admin_token.as_deref() else {
+        return Err(ApiError::Forbidden("admin endpoints are disabled".to_string()));
+    };
+
+    let authorized = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let Some(backup) = state.config.backup.as_ref() else {
+        return Err(ApiError::NotConfigured("backups are not configured".to_string()));
+    };
+
+    let path = backup_now(&state.db, backup).await?;
+    info!("database backed up to {}", path.display());
+
+    Ok(path.display().to_string().into_response())
+}
+
+/// Compares two secrets in time independent of where they differ, so that a token can't be guessed
+/// byte by byte from response times
+///
+/// # Arguments
+///
+/// * 'a' - first secret
+/// * 'b' - second secret
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -1,32 +1,138 @@
 use std::{env, fs};
+use chrono_tz::Tz;
 use log::LevelFilter;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use crate::errors::ConfigError;
+use crate::local_time::DEFAULT_TIME_ZONE;
 use crate::logging::setup_logger;
+use crate::manager_db::retention::RetentionPolicy;
+use crate::units::{UnitSystem, Units};
 
 #[derive(Deserialize, Clone)]
 pub struct WebServerParameters {
     pub bind_address: String,
     pub bind_port: u16,
+    pub admin_token: Option<String>,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Temperature {
     pub sensor: Vec<String>,
     pub name: String,
+    pub display_name: Option<String>,
+    /// Name of the weather forecast to get wind and humidity from, defaults to the first one configured
+    #[serde(default)]
+    pub forecast: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForecastProvider {
+    Smhi,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct WeatherForecast {
     pub name: String,
+    pub display_name: Option<String>,
+    #[serde(default = "default_provider")]
+    pub provider: ForecastProvider,
     pub lat: f64,
     pub long: f64,
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval_secs: u64,
+    #[serde(default = "default_forecast_days")]
+    pub days: i64,
+}
+
+/// Max number of days of a weather forecast, both configured and requested
+pub const MAX_FORECAST_DAYS: i64 = 1000;
+
+fn default_provider() -> ForecastProvider { ForecastProvider::Smhi }
+fn default_refresh_interval() -> u64 { 3600 }
+fn default_forecast_days() -> i64 { 2 }
+fn default_pool_size() -> usize { 4 }
+fn default_backup_interval() -> i64 { 24 }
+fn default_keep_daily() -> usize { 7 }
+fn default_keep_weekly() -> usize { 4 }
+fn default_time_zone() -> Tz { DEFAULT_TIME_ZONE }
+fn default_influx_batch_size() -> usize { 500 }
+fn default_influx_flush_interval() -> u64 { 10 }
+fn default_influx_max_buffer() -> u64 { 50 }
+
+#[derive(Deserialize, Clone, Default)]
+pub struct Retention {
+    pub observation_max_age_in_days: Option<i64>,
+    pub forecast_max_age_in_days: Option<i64>,
+    pub hourly_max_age_in_days: Option<i64>,
+    pub daily_max_age_in_days: Option<i64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Sqlite,
+    Postgres,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct DB {
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default)]
     pub db_path: String,
+    pub postgres_url: Option<String>,
     pub max_age_in_days: i64,
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+impl DB {
+    /// Returns the retention policy, where raw observations and forecasts fall back on
+    /// `max_age_in_days` and rollups are kept indefinitely unless configured otherwise
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            observation_days: self.retention.observation_max_age_in_days.unwrap_or(self.max_age_in_days),
+            forecast_days: self.retention.forecast_max_age_in_days.unwrap_or(self.max_age_in_days),
+            hourly_days: self.retention.hourly_max_age_in_days,
+            daily_days: self.retention.daily_max_age_in_days,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Backup {
+    pub backup_dir: String,
+    #[serde(default = "default_backup_interval")]
+    pub interval_hours: i64,
+    #[serde(default = "default_keep_daily")]
+    pub keep_daily: usize,
+    #[serde(default = "default_keep_weekly")]
+    pub keep_weekly: usize,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct InfluxDB {
+    pub url: String,
+    pub token: Option<String>,
+    #[serde(default = "default_influx_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_influx_flush_interval")]
+    pub flush_interval_secs: u64,
+    pub buffer_dir: String,
+    #[serde(default = "default_influx_max_buffer")]
+    pub max_buffer_mb: u64,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct UnitSettings {
+    #[serde(default)]
+    pub default: UnitSystem,
+    #[serde(default)]
+    pub custom: Units,
 }
 
 #[derive(Deserialize, Clone)]
@@ -34,28 +140,97 @@ pub struct General {
     pub log_path: String,
     pub log_level: LevelFilter,
     pub log_to_stdout: bool,
+    #[serde(default = "default_time_zone")]
+    pub time_zone: Tz,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub web_server: WebServerParameters,
     pub temperature: Temperature,
-    pub weather_forecast: WeatherForecast,
+    #[serde(deserialize_with = "one_or_many")]
+    pub weather_forecast: Vec<WeatherForecast>,
     pub db: DB,
+    pub backup: Option<Backup>,
+    pub influxdb: Option<InfluxDB>,
+    #[serde(default)]
+    pub units: UnitSettings,
     pub general: General,
 }
 
+/// A table given either once or as an array of tables
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// Deserializes a table given either once, e.g. the single [weather_forecast] of configurations
+/// from before multiple forecasts were supported, or as an array of tables
+///
+/// # Arguments
+///
+/// * 'deserializer' - the deserializer
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => vec![item],
+        OneOrMany::Many(items) => items,
+    })
+}
+
+/// Command given as the first non option argument, defaults to serve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Run the logger and web server
+    Serve,
+    /// Migrate the database schema to the latest version and exit
+    Migrate,
+    /// Export observations and/or forecasts as csv or ndjson and exit
+    Export,
+    /// Import historical observations from csv or ndjson and exit
+    Import,
+    /// Back up the database to the configured backup directory and exit
+    Backup,
+}
+
+/// Returns the command given on the command line
+///
+pub fn command() -> Result<Command, ConfigError> {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.iter().find(|a| !a.starts_with("--")).map(|a| a.as_str()) {
+        None | Some("serve") => Ok(Command::Serve),
+        Some("migrate") => Ok(Command::Migrate),
+        Some("export") => Ok(Command::Export),
+        Some("import") => Ok(Command::Import),
+        Some("backup") => Ok(Command::Backup),
+        Some(other) => Err(ConfigError(format!("unknown command: {}", other))),
+    }
+}
+
+/// Returns the value of a command line option given as --<name>=<value>
+///
+/// # Arguments
+///
+/// * 'name' - name of the option without leading dashes
+pub fn option(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+
+    env::args()
+        .find(|a| a.starts_with(&prefix))
+        .map(|a| a[prefix.len()..].to_string())
+}
+
 /// Returns a configuration struct for the application and starts logging
 ///
 pub fn config() -> Result<Config, ConfigError> {
-    let args: Vec<String> = env::args().collect();
-    let config_path = args.iter()
-        .find(|p| p.starts_with("--config="))
+    let config_path = option("config")
         .ok_or(ConfigError::from("missing --config=<config_path>"))?;
-    let config_path = config_path
-        .split_once('=')
-        .ok_or(ConfigError::from("invalid --config=<config_path>"))?
-        .1;
 
     let config = load_config(&config_path)?;
 
@@ -70,9 +245,146 @@ pub fn config() -> Result<Config, ConfigError> {
 ///
 /// * 'config_path' - path to the config file
 fn load_config(config_path: &str) -> Result<Config, ConfigError> {
-
     let toml = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&toml)?;
+
+    parse_config(&toml)
+}
+
+/// Parses and validates the configuration
+///
+/// # Arguments
+///
+/// * 'toml' - the configuration file contents
+fn parse_config(toml: &str) -> Result<Config, ConfigError> {
+    let mut config: Config = toml::from_str(toml)?;
+
+    if config.temperature.forecast.is_empty() {
+        config.temperature.forecast = config.weather_forecast.first()
+            .map(|fc| fc.name.clone())
+            .ok_or(ConfigError::from("no weather forecast configured"))?;
+    }
+
+    for (i, fc) in config.weather_forecast.iter().enumerate() {
+        if config.weather_forecast[..i].iter().any(|other| other.name == fc.name) {
+            return Err(ConfigError(format!("duplicate weather forecast name: {}", fc.name)));
+        }
+        if !(1..=MAX_FORECAST_DAYS).contains(&fc.days) {
+            return Err(ConfigError(format!("days of weather forecast {} must be between 1 and {}", fc.name, MAX_FORECAST_DAYS)));
+        }
+    }
+
+    match config.db.backend {
+        Backend::Sqlite if config.db.db_path.is_empty() => return Err(ConfigError::from("missing db_path for the sqlite backend")),
+        Backend::Postgres if config.db.postgres_url.is_none() => return Err(ConfigError::from("missing postgres_url for the postgres backend")),
+        _ => {},
+    }
+
+    if config.backup.is_some() && config.db.backend != Backend::Sqlite {
+        return Err(ConfigError::from("backups are only supported for the sqlite backend"));
+    }
+
+    if !config.weather_forecast.iter().any(|fc| fc.name == config.temperature.forecast) {
+        return Err(ConfigError(format!("unknown weather forecast for temperature: {}", config.temperature.forecast)));
+    }
 
     Ok(config)
 }
+
+impl Config {
+    /// Returns the configured weather forecast with the given name, if any
+    ///
+    /// # Arguments
+    ///
+    /// * 'name' - name of the weather forecast
+    pub fn forecast(&self, name: &str) -> Option<&WeatherForecast> {
+        self.weather_forecast.iter().find(|fc| fc.name == name)
+    }
+
+    /// Returns the names of this instance's observation and forecast sources, which are the ones it applies
+    /// retention to when sharing a database with other instances
+    pub fn sources(&self) -> Vec<String> {
+        std::iter::once(self.temperature.name.clone())
+            .chain(self.weather_forecast.iter().map(|fc| fc.name.clone()))
+            .collect()
+    }
+
+    /// Returns the units of the requested unit system, or of the configured default one if not requested
+    ///
+    /// # Arguments
+    ///
+    /// * 'system' - the requested unit system
+    pub fn units(&self, system: Option<UnitSystem>) -> Units {
+        Units::of(system.unwrap_or(self.units.default), self.units.custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration as written for versions before multiple forecast locations were supported
+    const SINGLE_FORECAST: &str = r#"
+        [web_server]
+        bind_address      = "127.0.0.1"
+        bind_port         = 8081
+
+        [temperature]
+        sensor            = ["http://localhost:8080/read"]
+        name              = "east_west"
+
+        [weather_forecast]
+        name              = "smhi"
+        lat               = 56.223306
+        long              = 15.658389
+
+        [db]
+        db_path           = "/tmp/weatherlogger.db"
+        max_age_in_days   = 366
+
+        [general]
+        log_path          = "/tmp/app.log"
+        log_level         = "Info"
+        log_to_stdout     = false
+    "#;
+
+    #[test]
+    fn accepts_single_forecast_table() {
+        let config = parse_config(SINGLE_FORECAST).unwrap();
+
+        assert_eq!(config.weather_forecast.len(), 1);
+        assert_eq!(config.weather_forecast[0].name, "smhi");
+        assert_eq!(config.weather_forecast[0].provider, ForecastProvider::Smhi);
+        assert_eq!(config.temperature.forecast, "smhi");
+    }
+
+    #[test]
+    fn accepts_forecast_array_and_defaults_to_first() {
+        let toml = SINGLE_FORECAST
+            .replace("[weather_forecast]", "[[weather_forecast]]")
+            .replace("[db]", "[[weather_forecast]]\nname = \"cabin\"\nlat = 57.1\nlong = 16.5\n\n[db]");
+
+        let config = parse_config(&toml).unwrap();
+
+        assert_eq!(config.weather_forecast.len(), 2);
+        assert_eq!(config.forecast("cabin").map(|fc| fc.lat), Some(57.1));
+        assert_eq!(config.temperature.forecast, "smhi");
+    }
+
+    #[test]
+    fn rejects_forecast_days_out_of_range() {
+        for days in ["0", "-1", "1001", "9223372036854775807"] {
+            let toml = SINGLE_FORECAST.replace("long              = 15.658389", &format!("long = 15.658389\ndays = {}", days));
+            assert!(parse_config(&toml).is_err(), "{} days accepted", days);
+        }
+
+        let toml = SINGLE_FORECAST.replace("long              = 15.658389", "long = 15.658389\ndays = 10");
+        assert_eq!(parse_config(&toml).unwrap().weather_forecast[0].days, 10);
+    }
+
+    #[test]
+    fn rejects_unknown_temperature_forecast() {
+        let toml = SINGLE_FORECAST.replace("name              = \"east_west\"", "name = \"east_west\"\nforecast = \"cabin\"");
+
+        assert!(parse_config(&toml).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use crate::manager_db::models::ForecastRecord;
+
+/// Max distance in time to stored forecast records used when interpolating,
+/// which covers the 6-hourly spacing SMHI uses for the later part of their forecasts
+pub const MAX_INTERPOLATION_DISTANCE: TimeDelta = TimeDelta::hours(6);
+
+/// Returns a forecast record for the given time interpolated from a time ordered slice of records.
+/// If there is a record at exactly the given time it is returned as is, and if there are no records
+/// on both sides within `MAX_INTERPOLATION_DISTANCE` None is returned.
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+/// * 'date_time' - the time to interpolate for
+pub fn interpolate_at(records: &[ForecastRecord], date_time: DateTime<Utc>) -> Option<ForecastRecord> {
+    let idx = records.partition_point(|r| r.date_time < date_time);
+    let after = records.get(idx);
+
+    if let Some(after) = after.filter(|a| a.date_time == date_time) {
+        return Some(after.clone());
+    }
+
+    let before = records.get(idx.checked_sub(1)?)?;
+    let after = after?;
+    if date_time - before.date_time > MAX_INTERPOLATION_DISTANCE || after.date_time - date_time > MAX_INTERPOLATION_DISTANCE {
+        return None;
+    }
+
+    Some(interpolate(before, after, date_time))
+}
+
+/// Interpolates a forecast record for a time between two stored forecast records.
+/// Temperature, wind speed, humidity, cloud indexes and precipitation are interpolated linearly while the
+/// symbol code, which is a category rather than a quantity, is taken from the nearest record.
+///
+/// # Arguments
+///
+/// * 'before' - the forecast record at or before the requested time
+/// * 'after' - the forecast record at or after the requested time
+/// * 'date_time' - the time to interpolate for
+pub fn interpolate(before: &ForecastRecord, after: &ForecastRecord, date_time: DateTime<Utc>) -> ForecastRecord {
+    let span = (after.date_time - before.date_time).num_seconds();
+    let fraction = if span > 0 {
+        ((date_time - before.date_time).num_seconds() as f64 / span as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let nearest = if fraction < 0.5 { before } else { after };
+
+    ForecastRecord {
+        date_time,
+        temperature: lerp(before.temperature, after.temperature, fraction),
+        wind_speed: lerp(before.wind_speed, after.wind_speed, fraction),
+        humidity: lerp_u8(before.humidity, after.humidity, fraction),
+        lcc_mean: lerp_u8(before.lcc_mean, after.lcc_mean, fraction),
+        mcc_mean: lerp_u8(before.mcc_mean, after.mcc_mean, fraction),
+        hcc_mean: lerp_u8(before.hcc_mean, after.hcc_mean, fraction),
+        symbol_code: nearest.symbol_code,
+        precipitation: lerp(before.precipitation, after.precipitation, fraction),
+        symbol: None,
+    }
+}
+
+/// Linear interpolation between two optional values, if only one is present that value is used
+///
+/// # Arguments
+///
+/// * 'a' - value at fraction 0
+/// * 'b' - value at fraction 1
+/// * 'fraction' - position between a and b (0 - 1)
+fn lerp(a: Option<f64>, b: Option<f64>, fraction: f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + (b - a) * fraction),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Linear interpolation between two optional integer values, rounded to the nearest integer
+///
+/// # Arguments
+///
+/// * 'a' - value at fraction 0
+/// * 'b' - value at fraction 1
+/// * 'fraction' - position between a and b (0 - 1)
+fn lerp_u8(a: Option<u8>, b: Option<u8>, fraction: f64) -> Option<u8> {
+    lerp(a.map(f64::from), b.map(f64::from), fraction).map(|v| v.round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hour: i64, temperature: Option<f64>, humidity: Option<u8>, symbol_code: Option<u8>) -> ForecastRecord {
+        ForecastRecord {
+            date_time: time(hour),
+            temperature,
+            wind_speed: Some(4.0),
+            humidity,
+            lcc_mean: None,
+            mcc_mean: None,
+            hcc_mean: None,
+            symbol_code,
+            precipitation: None,
+            symbol: None,
+        }
+    }
+
+    fn time(hour: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(hour * 3600, 0).unwrap()
+    }
+
+    #[test]
+    fn returns_exact_hit_as_is() {
+        let records = [record(0, Some(1.0), None, Some(1)), record(1, Some(3.0), None, Some(2))];
+
+        let result = interpolate_at(&records, time(1)).unwrap();
+
+        assert_eq!(result.date_time, time(1));
+        assert_eq!(result.temperature, Some(3.0));
+        assert_eq!(result.symbol_code, Some(2));
+    }
+
+    #[test]
+    fn interpolates_between_records() {
+        let records = [record(0, Some(2.0), Some(50), Some(1)), record(4, Some(6.0), Some(71), Some(3))];
+
+        let result = interpolate_at(&records, time(1)).unwrap();
+        assert_eq!(result.date_time, time(1));
+        assert_eq!(result.temperature, Some(3.0));
+        assert_eq!(result.humidity, Some(55));
+        assert_eq!(result.wind_speed, Some(4.0));
+        assert_eq!(result.symbol_code, Some(1));
+
+        assert_eq!(interpolate_at(&records, time(3)).unwrap().symbol_code, Some(3));
+    }
+
+    #[test]
+    fn returns_none_outside_records() {
+        let records = [record(1, Some(2.0), None, None), record(2, Some(3.0), None, None)];
+
+        assert!(interpolate_at(&records, time(0)).is_none());
+        assert!(interpolate_at(&records, time(3)).is_none());
+        assert!(interpolate_at(&[], time(1)).is_none());
+    }
+
+    #[test]
+    fn returns_none_across_gaps_beyond_max_distance() {
+        let records = [record(0, Some(2.0), None, None), record(13, Some(3.0), None, None)];
+        assert!(interpolate_at(&records, time(6)).is_none());
+        assert!(interpolate_at(&records, time(7)).is_none());
+
+        let records = [record(0, Some(2.0), None, None), record(12, Some(14.0), None, None)];
+        assert_eq!(interpolate_at(&records, time(6)).unwrap().temperature, Some(8.0));
+    }
+
+    #[test]
+    fn uses_the_present_value_when_one_is_missing() {
+        let before = record(0, None, Some(40), None);
+        let after = record(2, Some(5.0), None, Some(4));
+
+        let result = interpolate(&before, &after, time(1));
+
+        assert_eq!(result.temperature, Some(5.0));
+        assert_eq!(result.humidity, Some(40));
+        assert_eq!(result.symbol_code, Some(4));
+        assert_eq!(result.precipitation, None);
+    }
+
+    #[test]
+    fn lerp_falls_back_to_the_present_value() {
+        assert_eq!(lerp(Some(1.0), Some(3.0), 0.25), Some(1.5));
+        assert_eq!(lerp(Some(1.0), None, 0.75), Some(1.0));
+        assert_eq!(lerp(None, Some(3.0), 0.25), Some(3.0));
+        assert_eq!(lerp(None, None, 0.5), None);
+        assert_eq!(lerp_u8(Some(0), Some(3), 0.5), Some(2));
+    }
+}
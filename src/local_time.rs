@@ -0,0 +1,182 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+/// Time zone used for local calendar days unless another one is configured
+pub const DEFAULT_TIME_ZONE: Tz = chrono_tz::Europe::Stockholm;
+
+/// Max number of days of a 'last<N>d' range
+const MAX_NAMED_RANGE_DAYS: i64 = 366;
+
+/// Returns the UTC time at which the given local calendar day starts.
+/// Should midnight not exist due to a DST transition the first existing local time after it is used.
+///
+/// # Arguments
+///
+/// * 'date' - local calendar date
+/// * 'tz' - time zone the date is local to
+pub fn start_of_day(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+
+    (0..24)
+        .find_map(|h| tz.from_local_datetime(&(midnight + chrono::TimeDelta::hours(h))).earliest())
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+/// Returns the local calendar date for the given time
+///
+/// # Arguments
+///
+/// * 'date_time' - the time in UTC
+/// * 'tz' - time zone to get the local date in
+pub fn local_date(date_time: DateTime<Utc>, tz: Tz) -> NaiveDate {
+    date_time.with_timezone(&tz).date_naive()
+}
+
+/// Returns the start and end (non-inclusive) of a local calendar day, which is 23 or 25 hours long on DST transitions
+///
+/// # Arguments
+///
+/// * 'date' - local calendar date
+/// * 'tz' - time zone the date is local to
+pub fn day_range(date: NaiveDate, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    (start_of_day(date, tz), start_of_day(date + TimeDelta::days(1), tz))
+}
+
+/// Returns a named range of whole local calendar days, i.e. 'today', 'yesterday', 'tomorrow' or
+/// 'last<N>d' for the last N days including today, or None if the name isn't known
+///
+/// # Arguments
+///
+/// * 'name' - name of the range
+/// * 'now' - the current time
+/// * 'tz' - time zone of the calendar days
+pub fn named_range(name: &str, now: DateTime<Utc>, tz: Tz) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let today = local_date(now, tz);
+
+    let (first, last) = match name {
+        "today" => (today, today),
+        "yesterday" => (today - TimeDelta::days(1), today - TimeDelta::days(1)),
+        "tomorrow" => (today + TimeDelta::days(1), today + TimeDelta::days(1)),
+        name => {
+            let days: i64 = name.strip_prefix("last")?.strip_suffix('d')?.parse().ok()
+                .filter(|days| (1..=MAX_NAMED_RANGE_DAYS).contains(days))?;
+            (today - TimeDelta::days(days - 1), today)
+        },
+    };
+
+    Some((day_range(first, tz).0, day_range(last, tz).1))
+}
+
+/// Local calendar periods that series can be divided into
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl CalendarPeriod {
+    /// Returns the first date of the period containing the given date, weeks start on Monday
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - local calendar date
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            CalendarPeriod::Day => date,
+            CalendarPeriod::Week => date - TimeDelta::days(date.weekday().num_days_from_monday() as i64),
+            CalendarPeriod::Month => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    /// Returns the first date of the period following the one starting at the given date
+    ///
+    /// # Arguments
+    ///
+    /// * 'start' - first date of a period
+    pub fn next(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            CalendarPeriod::Day => start + TimeDelta::days(1),
+            CalendarPeriod::Week => start + TimeDelta::days(7),
+            CalendarPeriod::Month => start + Months::new(1),
+        }
+    }
+
+    /// Returns the shortest length of the period in days
+    ///
+    pub fn min_days(&self) -> i64 {
+        match self {
+            CalendarPeriod::Day => 1,
+            CalendarPeriod::Week => 7,
+            CalendarPeriod::Month => 28,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{America::Santiago, Europe::Stockholm};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn days_are_23_hours_when_dst_starts() {
+        let (start, end) = day_range(date(2025, 3, 30), Stockholm);
+
+        assert_eq!(start, time("2025-03-29T23:00:00Z"));
+        assert_eq!(end, time("2025-03-30T22:00:00Z"));
+        assert_eq!(end - start, TimeDelta::hours(23));
+        assert_eq!(local_date(end - TimeDelta::seconds(1), Stockholm), date(2025, 3, 30));
+    }
+
+    #[test]
+    fn days_are_25_hours_when_dst_ends() {
+        let (start, end) = day_range(date(2025, 10, 26), Stockholm);
+
+        assert_eq!(start, time("2025-10-25T22:00:00Z"));
+        assert_eq!(end, time("2025-10-26T23:00:00Z"));
+        assert_eq!(end - start, TimeDelta::hours(25));
+    }
+
+    #[test]
+    fn days_without_midnight_start_at_the_first_existing_time() {
+        // Clocks in Chile skip from 00:00 to 01:00 when DST starts
+        assert_eq!(start_of_day(date(2023, 9, 3), Santiago), time("2023-09-03T01:00:00-03:00"));
+
+        let (start, end) = day_range(date(2023, 9, 2), Santiago);
+        assert_eq!((start, end), (time("2023-09-02T00:00:00-04:00"), time("2023-09-03T01:00:00-03:00")));
+        assert_eq!(end - start, TimeDelta::hours(24));
+
+        let (start, end) = day_range(date(2023, 9, 3), Santiago);
+        assert_eq!(end - start, TimeDelta::hours(23));
+    }
+
+    #[test]
+    fn named_ranges_span_whole_local_days_across_transitions() {
+        // Just after local midnight following the spring transition
+        let now = time("2025-03-30T22:30:00Z");
+        assert_eq!(named_range("yesterday", now, Stockholm), Some(day_range(date(2025, 3, 30), Stockholm)));
+        assert_eq!(named_range("today", now, Stockholm), Some(day_range(date(2025, 3, 31), Stockholm)));
+
+        let now = time("2025-10-25T12:00:00Z");
+        assert_eq!(named_range("tomorrow", now, Stockholm), Some(day_range(date(2025, 10, 26), Stockholm)));
+
+        let (start, end) = named_range("last2d", time("2025-10-27T12:00:00Z"), Stockholm).unwrap();
+        assert_eq!((start, end), (time("2025-10-25T22:00:00Z"), time("2025-10-27T23:00:00Z")));
+        assert_eq!(end - start, TimeDelta::hours(49));
+
+        assert_eq!(named_range("last0d", now, Stockholm), None);
+        assert_eq!(named_range("last367d", now, Stockholm), None);
+        assert_eq!(named_range("lastweek", now, Stockholm), None);
+    }
+}
@@ -6,53 +6,171 @@ mod handlers;
 mod manager_temperature;
 mod manager_smhi;
 mod manager_forecast;
+mod manager_backup;
+mod manager_import;
+mod manager_influx;
 mod perceived_temperature;
+mod interpolation;
+mod resample;
+mod local_time;
+mod sun_position;
+mod weather_symbols;
+mod negotiation;
+mod statistics;
+mod units;
+mod events;
+mod websocket;
 
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 use axum::Router;
-use axum::routing::get;
-use log::error;
-use tokio::sync::Mutex;
+use axum::routing::{get, post};
+use log::{error, info};
 use crate::errors::UnrecoverableError;
-use crate::handlers::{forecast, min_max, temperature};
-use crate::initialization::config;
+use crate::events::Events;
+use crate::handlers::{admin_backup, current, daily_forecast, export, forecast, forecast_at, min_max, sources, stream, temperature};
+use crate::initialization::{command, config, option, Backend, Command, Config};
+use crate::manager_backup::{backup_file, run_backups};
 use crate::manager_db::DB;
+use crate::manager_db::export::{export_database, Export};
 use crate::manager_forecast::run_forecasts;
+use crate::manager_import::{import, ImportOptions};
 use crate::manager_temperature::run_observations;
+use crate::websocket::websocket;
 
-pub type SharedState = Arc<Mutex<DB>>;
+/// State shared between web handlers and background tasks
+#[derive(Clone)]
+pub struct SharedState {
+    pub db: DB,
+    pub config: Arc<Config>,
+    pub events: Events,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), UnrecoverableError> {
-    let config = config()?;
-    let state: SharedState = Arc::new(Mutex::new(DB::new(&config.db.db_path, config.db.max_age_in_days)?));
+    let command = command()?;
+    let config = Arc::new(config()?);
 
-    let c1_db = state.clone();
+    if matches!(command, Command::Export | Command::Import | Command::Backup) && config.db.backend != Backend::Sqlite {
+        return Err(UnrecoverableError("export, import and backup commands are only supported for the sqlite backend".to_string()));
+    }
+
+    match command {
+        Command::Serve => serve(config).await,
+        Command::Migrate => {
+            let (from, to) = manager_db::migrate(&config.db).await?;
+            info!("database schema migrated from version {} to {}", from, to);
+            println!("database schema migrated from version {} to {}", from, to);
+            Ok(())
+        },
+        Command::Export => export_to_output(&config),
+        Command::Import => {
+            import(&config.db.db_path, &ImportOptions::from_command_line()?)?;
+            Ok(())
+        },
+        Command::Backup => {
+            let backup = config.backup.as_ref()
+                .ok_or(UnrecoverableError("missing [backup] section in config".to_string()))?;
+            let path = backup_file(&config.db.db_path, backup)?;
+            info!("database backed up to {}", path.display());
+            println!("database backed up to {}", path.display());
+            Ok(())
+        },
+    }
+}
+
+/// Exports data as given by the command line options to stdout or to the file given by --output
+///
+/// # Arguments
+///
+/// * 'config' - application configuration
+fn export_to_output(config: &Config) -> Result<(), UnrecoverableError> {
+    let required = |name: &str| option(name)
+        .ok_or(UnrecoverableError(format!("missing --{}=<{}>", name, name)));
+
+    let export_request = Export::new(
+        &option("data").unwrap_or("observation".to_string()),
+        &required("id")?,
+        &required("from")?,
+        &required("to")?,
+        option("columns").as_deref(),
+        &option("format").unwrap_or("csv".to_string()),
+    )?;
+
+    let count = match option("output") {
+        Some(path) => export_database(&config.db.db_path, &export_request, &mut BufWriter::new(File::create(path)?))?,
+        None => export_database(&config.db.db_path, &export_request, &mut BufWriter::new(io::stdout().lock()))?,
+    };
+    info!("exported {} rows", count);
+
+    Ok(())
+}
+
+/// Runs sensor and forecast loops together with the web server
+///
+/// # Arguments
+///
+/// * 'config' - application configuration
+async fn serve(config: Arc<Config>) -> Result<(), UnrecoverableError> {
+    let mut db = manager_db::open(&config.db, config.sources()).await?;
+    if let Some(influxdb) = &config.influxdb {
+        info!("mirroring observations and forecasts to influxdb at {}", influxdb.url);
+        db = manager_influx::mirror(db, influxdb);
+    }
+
+    let state = SharedState {
+        db,
+        config: config.clone(),
+        events: events::channel(),
+    };
+
+    let c1_db = state.db.clone();
     tokio::spawn(async move {
         loop {
-            {
-                c1_db.lock().await.truncate_table();
-            }
+            c1_db.apply_retention().await;
             tokio::time::sleep(tokio::time::Duration::from_secs(86400)).await;
         }
     });
 
-    let c2_db = state.clone();
+    let c2_db = state.db.clone();
+    let c2_events = state.events.clone();
+    let c2_config = config.clone();
     tokio::spawn(async move {
-        run_observations(c2_db, &config.temperature.sensor, &config.temperature.name).await;
+        run_observations(c2_db, c2_events, &c2_config.temperature.sensor, &c2_config.temperature.name, &c2_config.temperature.forecast).await;
     });
 
-    let c3_db = state.clone();
-    tokio::spawn(async move {
-        run_forecasts(c3_db, config.weather_forecast.lat, config.weather_forecast.long, &config.weather_forecast.name).await;
-    });
+    for forecast in config.weather_forecast.iter().cloned() {
+        let c3_db = state.db.clone();
+        let c3_events = state.events.clone();
+        let tz = config.general.time_zone;
+        tokio::spawn(async move {
+            run_forecasts(c3_db, c3_events, forecast, tz).await;
+        });
+    }
+
+    if let Some(backup) = config.backup.clone() {
+        let c4_db = state.db.clone();
+        tokio::spawn(async move {
+            run_backups(c4_db, backup).await;
+        });
+    }
 
     let app = Router::new()
         .route("/temperature", get(temperature))
         .route("/minmax", get(min_max))
+        .route("/current", get(current))
+        .route("/sources", get(sources))
+        .route("/stream", get(stream))
+        .route("/ws", get(websocket))
         .route("/forecast", get(forecast))
+        .route("/forecast/at", get(forecast_at))
+        .route("/forecast/daily", get(daily_forecast))
+        .route("/export", get(export))
+        .route("/admin/backup", post(admin_backup))
         .with_state(state.clone());
 
     let ip_addr = Ipv4Addr::from_str(&config.web_server.bind_address).expect("invalid BIND_ADDR");
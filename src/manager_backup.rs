@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeDelta, Utc};
+use log::{error, info};
+use crate::errors::BackupError;
+use crate::initialization::Backup;
+use crate::manager_db::backup::backup_database;
+use crate::manager_db::DB;
+
+const BACKUP_PREFIX: &str = "weatherlogger-";
+const BACKUP_SUFFIX: &str = ".db";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Format of backup timestamps when parsing, which also accepts backups named without milliseconds
+const TIMESTAMP_PARSE_FORMAT: &str = "%Y%m%dT%H%M%S%.fZ";
+
+/// Backup loop, checks once an hour whether the latest backup is older than the configured interval
+/// and if so makes a new backup. This way a backup is made shortly after start should the
+/// application have been down when one was due.
+///
+/// # Arguments
+///
+/// * 'db' - database to back up
+/// * 'backup' - backup configuration
+pub async fn run_backups(db: DB, backup: Backup) {
+    loop {
+        let due = match list_backups(Path::new(&backup.backup_dir)) {
+            Ok(backups) => backups.first()
+                .is_none_or(|(time, _)| Utc::now() - *time >= TimeDelta::hours(backup.interval_hours)),
+            Err(e) => {
+                error!("failed to list backups: {}", e);
+                true
+            }
+        };
+
+        if due {
+            match backup_now(&db, &backup).await {
+                Ok(path) => info!("database backed up to {}", path.display()),
+                Err(e) => error!("failed to back up database: {}", e),
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
+    }
+}
+
+/// Makes a backup of the running database and rotates old backups, returns the path of the new backup
+///
+/// # Arguments
+///
+/// * 'db' - database to back up
+/// * 'backup' - backup configuration
+pub async fn backup_now(db: &DB, backup: &Backup) -> Result<PathBuf, BackupError> {
+    let path = new_backup_path(backup)?;
+    db.backup(path.clone()).await?;
+    rotate(backup)?;
+
+    Ok(path)
+}
+
+/// Makes a backup of the given database file and rotates old backups, returns the path of the new backup.
+/// Used from the command line, where there is no running `DB` instance.
+///
+/// # Arguments
+///
+/// * 'db_path' - full path to db file
+/// * 'backup' - backup configuration
+pub fn backup_file(db_path: &str, backup: &Backup) -> Result<PathBuf, BackupError> {
+    let path = new_backup_path(backup)?;
+    backup_database(db_path, &path)?;
+    rotate(backup)?;
+
+    Ok(path)
+}
+
+/// Returns a path for a new backup, named by the current time in milliseconds, and makes sure the backup
+/// directory exists. Should a backup by that name exist, the time is moved forward a millisecond at a time
+/// until the name is free.
+///
+/// # Arguments
+///
+/// * 'backup' - backup configuration
+fn new_backup_path(backup: &Backup) -> Result<PathBuf, BackupError> {
+    let dir = Path::new(&backup.backup_dir);
+    fs::create_dir_all(dir)?;
+
+    let mut time = Utc::now();
+    loop {
+        let path = dir.join(format!("{}{}{}", BACKUP_PREFIX, time.format(TIMESTAMP_FORMAT), BACKUP_SUFFIX));
+        if !path.exists() {
+            return Ok(path);
+        }
+        time += TimeDelta::milliseconds(1);
+    }
+}
+
+/// Deletes old backups. The newest backup of each of the `keep_daily` latest days with backups is kept,
+/// as is the newest backup of each of the `keep_weekly` latest (ISO) weeks with backups. The very newest
+/// backup is always kept.
+///
+/// # Arguments
+///
+/// * 'backup' - backup configuration
+fn rotate(backup: &Backup) -> Result<(), BackupError> {
+    let backups = list_backups(Path::new(&backup.backup_dir))?;
+
+    for path in backups_to_remove(&backups, backup.keep_daily, backup.keep_weekly) {
+        info!("removing old backup {}", path.display());
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the backups not kept by the rotation rules, see `rotate`
+///
+/// # Arguments
+///
+/// * 'backups' - all backups with the time they were made, newest first
+/// * 'keep_daily' - number of latest days to keep the newest backup of
+/// * 'keep_weekly' - number of latest weeks to keep the newest backup of
+fn backups_to_remove(backups: &[(DateTime<Utc>, PathBuf)], keep_daily: usize, keep_weekly: usize) -> Vec<&PathBuf> {
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    let mut keep = HashSet::new();
+
+    for (i, (time, path)) in backups.iter().enumerate() {
+        if i == 0 {
+            keep.insert(path);
+        }
+        if days.len() < keep_daily && days.insert(time.date_naive()) {
+            keep.insert(path);
+        }
+        let week = time.iso_week();
+        if weeks.len() < keep_weekly && weeks.insert((week.year(), week.week())) {
+            keep.insert(path);
+        }
+    }
+
+    backups.iter().map(|(_, path)| path).filter(|path| !keep.contains(path)).collect()
+}
+
+/// Returns all backups in the given directory with the time they were made, newest first
+///
+/// # Arguments
+///
+/// * 'dir' - backup directory
+fn list_backups(dir: &Path) -> Result<Vec<(DateTime<Utc>, PathBuf)>, BackupError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let timestamp = name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(BACKUP_SUFFIX)?;
+            let time = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_PARSE_FORMAT).ok()?.and_utc();
+
+            Some((time, entry.path()))
+        })
+        .collect::<Vec<(DateTime<Utc>, PathBuf)>>();
+
+    backups.sort_by_key(|b| std::cmp::Reverse(b.0));
+
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backups made at the given times (newest first), named by their time
+    fn backups(times: &[&str]) -> Vec<(DateTime<Utc>, PathBuf)> {
+        times.iter()
+            .map(|t| (DateTime::parse_from_rfc3339(t).unwrap().to_utc(), PathBuf::from(t)))
+            .collect()
+    }
+
+    fn removed(backups: &[(DateTime<Utc>, PathBuf)], keep_daily: usize, keep_weekly: usize) -> Vec<&str> {
+        backups_to_remove(backups, keep_daily, keep_weekly).iter().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn keeps_newest_backup_of_latest_days() {
+        let backups = backups(&[
+            "2026-01-07T12:00:00Z", "2026-01-07T06:00:00Z", "2026-01-06T12:00:00Z", "2026-01-05T12:00:00Z",
+        ]);
+
+        assert_eq!(removed(&backups, 2, 0), vec!["2026-01-07T06:00:00Z", "2026-01-05T12:00:00Z"]);
+    }
+
+    #[test]
+    fn keeps_newest_backup_of_latest_weeks() {
+        // 2026-01-04 is a Sunday, ending ISO week 1
+        let backups = backups(&[
+            "2026-01-13T12:00:00Z", "2026-01-12T12:00:00Z", "2026-01-04T12:00:00Z", "2026-01-03T12:00:00Z",
+            "2025-12-28T12:00:00Z",
+        ]);
+
+        assert_eq!(
+            removed(&backups, 1, 2),
+            vec!["2026-01-12T12:00:00Z", "2026-01-03T12:00:00Z", "2025-12-28T12:00:00Z"],
+        );
+    }
+
+    #[test]
+    fn always_keeps_newest_backup() {
+        let backups = backups(&["2026-01-07T12:00:00Z", "2026-01-06T12:00:00Z"]);
+
+        assert_eq!(removed(&backups, 0, 0), vec!["2026-01-06T12:00:00Z"]);
+    }
+
+    #[test]
+    fn parses_backup_names_with_and_without_milliseconds() {
+        let parse = |t| NaiveDateTime::parse_from_str(t, TIMESTAMP_PARSE_FORMAT).unwrap().and_utc();
+
+        assert_eq!(parse("20260107T120000Z"), DateTime::parse_from_rfc3339("2026-01-07T12:00:00Z").unwrap());
+        assert_eq!(parse("20260107T120000.250Z"), DateTime::parse_from_rfc3339("2026-01-07T12:00:00.250Z").unwrap());
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+use crate::manager_db::errors::DBError;
+
+/// Copies the database behind the given connection to a new database file using SQLite's online
+/// backup API. The copy is made in one step within a single read transaction, so it is a consistent
+/// snapshot, and since the database is in WAL mode it doesn't block writers meanwhile.
+///
+/// The copy is written to a temporary file next to the backup file and renamed when complete,
+/// so a failed backup never leaves a partial backup file behind.
+///
+/// # Arguments
+///
+/// * 'conn' - connection to the database to back up
+/// * 'dest' - path of the backup file, which must not already exist
+pub fn backup_to_file(conn: &Connection, dest: &Path) -> Result<(), DBError> {
+    if dest.exists() {
+        return Err(DBError::Other(format!("backup file already exists: {}", dest.display())));
+    }
+
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let result = copy_database(conn, &tmp).and_then(|_| Ok(fs::rename(&tmp, dest)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    result
+}
+
+/// Copies the database behind the given connection to the given file, replacing any previous content
+///
+/// # Arguments
+///
+/// * 'conn' - connection to the database to copy
+/// * 'dest' - path of the copy
+fn copy_database(conn: &Connection, dest: &Path) -> Result<(), DBError> {
+    if dest.exists() {
+        fs::remove_file(dest)?;
+    }
+
+    let mut dest_conn = Connection::open(dest)?;
+    Backup::new(conn, &mut dest_conn)?.step(-1)?;
+
+    Ok(())
+}
+
+/// Backs up a database file without going through a `DB` instance, e.g. from the command line
+/// while the application is running
+///
+/// # Arguments
+///
+/// * 'db_path' - full path to db file
+/// * 'dest' - path of the backup file, which must not already exist
+pub fn backup_database(db_path: &str, dest: &Path) -> Result<(), DBError> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI)?;
+
+    backup_to_file(&conn, dest)
+}
@@ -1,19 +1,53 @@
 use std::fmt;
 use std::fmt::Formatter;
 
-pub struct DBError(pub String);
+#[derive(Debug)]
+pub enum DBError {
+    /// No pooled connection became available in time, i.e. the database is busy rather than failing
+    PoolTimeout,
+    /// Any other error
+    Other(String),
+}
+
+impl DBError {
+    /// Returns the message of the error, without the prefix added when displayed
+    ///
+    pub fn message(&self) -> &str {
+        match self {
+            DBError::PoolTimeout => "timed out waiting for a database connection",
+            DBError::Other(message) => message,
+        }
+    }
+}
 
 impl fmt::Display for DBError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "DBError: {}", self.0)
+        write!(f, "DBError: {}", self.message())
     }
 }
 impl From<rusqlite::Error> for DBError {
-    fn from(err: rusqlite::Error) -> Self { DBError(err.to_string()) }
+    fn from(err: rusqlite::Error) -> Self { DBError::Other(err.to_string()) }
 }
 impl From<serde_json::Error> for DBError {
-    fn from(err: serde_json::Error) -> Self { DBError(err.to_string()) }
+    fn from(err: serde_json::Error) -> Self { DBError::Other(err.to_string()) }
 }
 impl From<chrono::format::ParseError> for DBError {
-    fn from(err: chrono::format::ParseError) -> Self { DBError(err.to_string()) }
-}
\ No newline at end of file
+    fn from(err: chrono::format::ParseError) -> Self { DBError::Other(err.to_string()) }
+}
+impl From<std::io::Error> for DBError {
+    fn from(err: std::io::Error) -> Self { DBError::Other(err.to_string()) }
+}
+impl From<tokio_postgres::Error> for DBError {
+    fn from(err: tokio_postgres::Error) -> Self { DBError::Other(err.to_string()) }
+}
+impl From<deadpool_postgres::PoolError> for DBError {
+    fn from(err: deadpool_postgres::PoolError) -> Self {
+        match err {
+            deadpool_postgres::PoolError::Timeout(_) => DBError::PoolTimeout,
+            err => DBError::Other(err.to_string()),
+        }
+    }
+}
+impl From<deadpool_postgres::BuildError> for DBError {
+    fn from(err: deadpool_postgres::BuildError) -> Self { DBError::Other(err.to_string()) }
+}
@@ -0,0 +1,343 @@
+use std::io::Write;
+use chrono::{DateTime, Utc};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use crate::manager_db::errors::DBError;
+
+/// Columns of the observation table available for export
+const OBSERVATION_COLUMNS: &[&str] = &["source", "datetime", "temperature", "perceived_temperature", "humidity"];
+
+/// Columns of the forecast table available for export
+const FORECAST_COLUMNS: &[&str] = &[
+    "source", "datetime", "temperature", "humidity", "wind_speed",
+    "lcc_mean", "mcc_mean", "hcc_mean", "symbol_code", "precipitation",
+];
+
+/// Pseudo column telling whether a row is an observation or a forecast
+const KIND_COLUMN: &str = "kind";
+
+/// Number of rows read by each query of an export, so that no connection or read transaction
+/// is held for long however large the export
+const EXPORT_PAGE_SIZE: u64 = 1000;
+
+/// Position of a row in an export, its time and 0 for observations or 1 for forecasts
+type ExportKey = (i64, i64);
+
+/// Output format of an export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// One json object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Parses a format given as csv or ndjson
+    ///
+    /// # Arguments
+    ///
+    /// * 'format' - format name
+    pub fn parse(format: &str) -> Result<Self, DBError> {
+        match format {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            other => Err(DBError::Other(format!("unknown export format: {}", other))),
+        }
+    }
+
+    /// Returns the mime type of the format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Returns the file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// Data to export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportData {
+    Observation,
+    Forecast,
+    /// Both observations and forecasts, told apart by the kind column
+    All,
+}
+
+impl ExportData {
+    /// Parses the data to export given as observation, forecast or all
+    ///
+    /// # Arguments
+    ///
+    /// * 'data' - name of the data
+    pub fn parse(data: &str) -> Result<Self, DBError> {
+        match data {
+            "observation" => Ok(ExportData::Observation),
+            "forecast" => Ok(ExportData::Forecast),
+            "all" => Ok(ExportData::All),
+            other => Err(DBError::Other(format!("unknown export data: {}", other))),
+        }
+    }
+
+    /// Returns all columns available for this data, in their default order
+    fn columns(&self) -> Vec<&'static str> {
+        match self {
+            ExportData::Observation => OBSERVATION_COLUMNS.to_vec(),
+            ExportData::Forecast => FORECAST_COLUMNS.to_vec(),
+            ExportData::All => {
+                let mut columns = vec![KIND_COLUMN];
+                columns.extend(FORECAST_COLUMNS);
+                columns.push("perceived_temperature");
+                columns
+            }
+        }
+    }
+}
+
+/// A validated export request
+#[derive(Clone, Debug)]
+pub struct Export {
+    pub data: ExportData,
+    pub source: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub columns: Vec<&'static str>,
+    pub format: ExportFormat,
+}
+
+impl Export {
+    /// Creates an export request, validating all arguments
+    ///
+    /// # Arguments
+    ///
+    /// * 'data' - observation, forecast or all
+    /// * 'source' - sensor id or forecast name (source)
+    /// * 'from' - utc datetime in the rfc3339 format
+    /// * 'to' - utc datetime in the rfc3339 format (non-inclusive)
+    /// * 'columns' - comma separated list of columns, all columns if not given
+    /// * 'format' - csv or ndjson
+    pub fn new(data: &str, source: &str, from: &str, to: &str, columns: Option<&str>, format: &str) -> Result<Self, DBError> {
+        let data = ExportData::parse(data)?;
+        let available = data.columns();
+
+        let columns = match columns {
+            Some(columns) => columns.split(',')
+                .map(|c| available.iter()
+                    .find(|a| **a == c.trim())
+                    .copied()
+                    .ok_or(DBError::Other(format!("unknown column: {}", c.trim()))))
+                .collect::<Result<Vec<&'static str>, DBError>>()?,
+            None => available,
+        };
+
+        Ok(Export {
+            data,
+            source: source.to_string(),
+            from: DateTime::parse_from_rfc3339(from)?.with_timezone(&Utc),
+            to: DateTime::parse_from_rfc3339(to)?.with_timezone(&Utc),
+            columns,
+            format: ExportFormat::parse(format)?,
+        })
+    }
+
+    /// Returns a file name suitable for the export
+    pub fn file_name(&self) -> String {
+        format!("{}-{}-{}.{}", self.source, self.from.format("%Y%m%d"), self.to.format("%Y%m%d"), self.format.extension())
+    }
+
+    /// Returns the query selecting a page of the requested columns after a given position, ordered by time
+    /// with observations before forecasts at the same time. The position of each row is given by the two
+    /// columns following the requested ones.
+    /// Only column names from the fixed lists above ever make it into the statement.
+    fn sql(&self) -> String {
+        let select = |table: &str, kind: &str, sort_kind: i64, table_columns: &[&str]| {
+            let columns = self.columns.iter()
+                .map(|c| match *c {
+                    KIND_COLUMN => format!("'{}' AS kind", kind),
+                    c if table_columns.contains(&c) => c.to_string(),
+                    c => format!("NULL AS {}", c),
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            format!(
+                "SELECT {columns}, datetime AS sort_time, {sort_kind} AS sort_kind FROM {table}
+                    WHERE source = ?1 AND datetime >= ?2 AND datetime < ?3
+                        AND (datetime > ?4 OR (datetime = ?4 AND {sort_kind} > ?5))"
+            )
+        };
+
+        let observations = select("observation", "observation", 0, OBSERVATION_COLUMNS);
+        let forecasts = select("forecast", "forecast", 1, FORECAST_COLUMNS);
+
+        let union = match self.data {
+            ExportData::Observation => observations,
+            ExportData::Forecast => forecasts,
+            ExportData::All => format!("{} UNION ALL {}", observations, forecasts),
+        };
+
+        format!("{} ORDER BY sort_time, sort_kind LIMIT ?6", union)
+    }
+}
+
+/// Writes the requested data to the given writer in pages of `EXPORT_PAGE_SIZE` rows, so that large ranges
+/// are never held in memory. Each page is read by a query of its own, with the connection given by 'read'
+/// only while the page is read and not while it is written. Returns the number of exported rows.
+///
+/// # Arguments
+///
+/// * 'read' - runs the given function with a database connection
+/// * 'export' - what to export and how
+/// * 'out' - writer receiving the export
+pub fn export(
+    mut read: impl FnMut(&mut dyn FnMut(&Connection) -> Result<(), DBError>) -> Result<(), DBError>,
+    export: &Export,
+    out: &mut impl Write,
+) -> Result<u64, DBError> {
+    if export.format == ExportFormat::Csv {
+        writeln!(out, "{}", export.columns.join(","))?;
+    }
+
+    let mut count = 0;
+    let mut after = (i64::MIN, 0);
+    loop {
+        let mut page = Vec::new();
+        let (mut rows, mut last) = (0, None);
+        read(&mut |conn| {
+            (rows, last) = export_page(conn, export, after, &mut page)?;
+            Ok(())
+        })?;
+
+        out.write_all(&page)?;
+        count += rows;
+
+        match last {
+            Some(key) if rows == EXPORT_PAGE_SIZE => after = key,
+            _ => break,
+        }
+    }
+
+    out.flush()?;
+
+    Ok(count)
+}
+
+/// Writes one page of the requested data after the given position, returns the number of rows
+/// and the position of the last one
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+/// * 'export' - what to export and how
+/// * 'after' - position of the last row of the previous page
+/// * 'out' - writer receiving the page
+fn export_page(conn: &Connection, export: &Export, after: ExportKey, out: &mut impl Write) -> Result<(u64, Option<ExportKey>), DBError> {
+    let mut stmt = conn.prepare_cached(&export.sql())?;
+    let mut rows = stmt.query(params![
+        export.source, export.from.timestamp(), export.to.timestamp(), after.0, after.1, EXPORT_PAGE_SIZE as i64,
+    ])?;
+
+    let mut count = 0;
+    let mut last = None;
+    while let Some(row) = rows.next()? {
+        let values = export.columns.iter().enumerate()
+            .map(|(i, column)| Ok((*column, format_value(column, row.get_ref(i)?, export.format))))
+            .collect::<Result<Vec<(&str, String)>, DBError>>()?;
+
+        match export.format {
+            ExportFormat::Csv => {
+                let line = values.into_iter().map(|(_, v)| v).collect::<Vec<String>>().join(",");
+                writeln!(out, "{}", line)?;
+            },
+            ExportFormat::Ndjson => {
+                let fields = values.into_iter()
+                    .map(|(c, v)| format!("\"{}\":{}", c, v))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                writeln!(out, "{{{}}}", fields)?;
+            },
+        }
+        last = Some((row.get(export.columns.len())?, row.get(export.columns.len() + 1)?));
+        count += 1;
+    }
+
+    Ok((count, last))
+}
+
+/// Formats one value for the given format. Datetimes are written in the rfc3339 format and
+/// nulls as empty csv fields or json nulls.
+///
+/// # Arguments
+///
+/// * 'column' - name of the column
+/// * 'value' - the value
+/// * 'format' - output format
+fn format_value(column: &str, value: ValueRef, format: ExportFormat) -> String {
+    let text = match (column, value) {
+        (_, ValueRef::Null) => None,
+        ("datetime", ValueRef::Integer(ts)) => DateTime::from_timestamp(ts, 0).map(|dt| dt.to_rfc3339()),
+        (_, ValueRef::Integer(i)) => return i.to_string(),
+        (_, ValueRef::Real(r)) => return r.to_string(),
+        (_, ValueRef::Text(t)) => Some(String::from_utf8_lossy(t).to_string()),
+        (_, ValueRef::Blob(_)) => None,
+    };
+
+    match (format, text) {
+        (ExportFormat::Csv, None) => String::new(),
+        (ExportFormat::Csv, Some(text)) if text.contains([',', '"', '\n', '\r']) => format!("\"{}\"", text.replace('"', "\"\"")),
+        (ExportFormat::Csv, Some(text)) => text,
+        (ExportFormat::Ndjson, None) => "null".to_string(),
+        (ExportFormat::Ndjson, Some(text)) => serde_json::Value::String(text).to_string(),
+    }
+}
+
+/// Exports from a database file without going through a `DB` instance, e.g. from the command line
+/// while the application is running
+///
+/// # Arguments
+///
+/// * 'db_path' - full path to db file
+/// * 'export' - what to export and how
+/// * 'out' - writer receiving the export
+pub fn export_database(db_path: &str, export_request: &Export, out: &mut impl Write) -> Result<u64, DBError> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI)?;
+
+    export(|f| f(&conn), export_request, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager_db::migrations;
+
+    #[test]
+    fn pages_through_observations_and_forecasts_in_order() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        for i in 0..1500 {
+            conn.execute("INSERT INTO observation (source, datetime, temperature) VALUES ('x', ?1, 1.0)", params![i * 60]).unwrap();
+            conn.execute("INSERT INTO forecast (source, datetime, temperature) VALUES ('x', ?1, 2.0)", params![i * 60]).unwrap();
+        }
+        let request = Export::new("all", "x", "1970-01-01T00:00:00Z", "1970-01-02T00:00:00Z", Some("kind,datetime"), "csv").unwrap();
+
+        let mut reads = 0;
+        let mut out = Vec::new();
+        let count = export(|f| { reads += 1; f(&conn) }, &request, &mut out).unwrap();
+
+        assert_eq!(count, 2880);
+        assert_eq!(reads, 3);
+        let lines = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2881);
+        assert_eq!(lines[1..3], ["observation,1970-01-01T00:00:00+00:00", "forecast,1970-01-01T00:00:00+00:00"]);
+        assert_eq!(lines[2880], "forecast,1970-01-01T23:59:00+00:00");
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+use rusqlite::{params, Connection};
+use crate::manager_db::errors::DBError;
+use crate::manager_db::migrations;
+use crate::manager_db::rollups::{add_to_rollups, is_archived, rebuild_rollups};
+
+/// One validated observation to import
+#[derive(Clone, Copy, Debug)]
+pub struct ImportRow {
+    pub timestamp: i64,
+    pub temperature: f64,
+    pub humidity: Option<u8>,
+    pub perceived_temperature: Option<f64>,
+}
+
+/// Outcome of an import
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportResult {
+    /// Rows inserted
+    pub inserted: u64,
+    /// Rows that already existed and were replaced
+    pub replaced: u64,
+    /// Rows that already existed and were left as they were
+    pub conflicts: u64,
+    /// Rows in days only kept in the rollups, which were left out since the rollups can't tell
+    /// whether they already hold them
+    pub archived: u64,
+}
+
+/// Bulk importer of historical observations into one source
+pub struct Importer {
+    conn: Connection,
+    source: String,
+    replace: bool,
+    days: BTreeSet<i64>,
+    archived_days: HashMap<i64, bool>,
+    result: ImportResult,
+}
+
+impl Importer {
+    /// Opens the database for import, migrating the schema to the latest version if needed.
+    /// Safe to use while the application is running, since the database is in WAL mode.
+    ///
+    /// # Arguments
+    ///
+    /// * 'db_path' - full path to db file
+    /// * 'source' - sensor id (source) to import observations into
+    /// * 'replace' - whether existing observations at the same time are replaced or kept
+    pub fn open(db_path: &str, source: &str, replace: bool) -> Result<Self, DBError> {
+        let mut conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        migrations::migrate(&mut conn)?;
+
+        Ok(Importer { conn, source: source.to_string(), replace, days: BTreeSet::new(), archived_days: HashMap::new(), result: ImportResult::default() })
+    }
+
+    /// Inserts a batch of rows in one transaction.
+    /// New rows are added to the rollups as they are inserted, just like live observations,
+    /// while rows in archived days are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * 'rows' - rows to insert
+    pub fn insert_batch(&mut self, rows: &[ImportRow]) -> Result<(), DBError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO observation (source, datetime, temperature, humidity, perceived_temperature)
+                    VALUES (?1, ?2, ?3, ?4, ?5)"
+            )?;
+            let mut update = tx.prepare(
+                "UPDATE observation SET temperature = ?3, humidity = ?4, perceived_temperature = ?5
+                    WHERE source = ?1 AND datetime = ?2"
+            )?;
+
+            for row in rows {
+                let values = params![self.source, row.timestamp, row.temperature, row.humidity, row.perceived_temperature];
+
+                let day = row.timestamp - row.timestamp.rem_euclid(86400);
+                let archived = match self.archived_days.get(&day) {
+                    Some(archived) => *archived,
+                    None => {
+                        let archived = is_archived(&tx, &self.source, row.timestamp)?;
+                        self.archived_days.insert(day, archived);
+                        archived
+                    },
+                };
+
+                if archived {
+                    self.result.archived += 1;
+                } else if insert.execute(values)? > 0 {
+                    add_to_rollups(&tx, &self.source, row.timestamp, row.temperature)?;
+                    self.days.insert(day);
+                    self.result.inserted += 1;
+                } else if self.replace {
+                    update.execute(values)?;
+                    self.days.insert(day);
+                    self.result.replaced += 1;
+                } else {
+                    self.result.conflicts += 1;
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Finishes the import by rebuilding the rollups of days where observations were inserted or replaced,
+    /// and returns the outcome
+    pub fn finish(mut self) -> Result<ImportResult, DBError> {
+        let tx = self.conn.transaction()?;
+        for day in &self.days {
+            rebuild_rollups(&tx, Some(&self.source), *day, day + 86400)?;
+        }
+        tx.commit()?;
+
+        Ok(self.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an importer on a new database file in the temporary directory, removed again by `remove`
+    fn importer(name: &str, replace: bool) -> (Importer, String) {
+        let path = std::env::temp_dir().join(format!("weatherlogger-importer-{}-{}.db", name, std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        remove(&path);
+
+        (Importer::open(&path, "east_west", replace).unwrap(), path)
+    }
+
+    fn remove(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    fn row(timestamp: i64, temperature: f64) -> ImportRow {
+        ImportRow { timestamp, temperature, humidity: None, perceived_temperature: None }
+    }
+
+    fn temperatures(conn: &Connection) -> Vec<(i64, f64)> {
+        let mut stmt = conn.prepare("SELECT datetime, temperature FROM observation ORDER BY datetime").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i64, f64)>, rusqlite::Error>>()
+            .unwrap()
+    }
+
+    fn import(replace: bool) -> (ImportResult, Vec<(i64, f64)>, (f64, f64)) {
+        let (mut importer, path) = importer(if replace { "replace" } else { "keep" }, replace);
+        importer.insert_batch(&[row(3600, 1.0), row(7200, 2.0)]).unwrap();
+        importer.insert_batch(&[row(7200, 5.0), row(10800, 3.0)]).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let result = importer.finish().unwrap();
+        let imported = temperatures(&conn);
+        let daily = conn.query_row("SELECT min, max FROM observation_daily WHERE bucket = 0", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        remove(&path);
+
+        (result, imported, daily)
+    }
+
+    #[test]
+    fn keeps_existing_rows_on_conflict() {
+        let (result, imported, daily) = import(false);
+
+        assert_eq!((result.inserted, result.replaced, result.conflicts), (3, 0, 1));
+        assert_eq!(imported, [(3600, 1.0), (7200, 2.0), (10800, 3.0)]);
+        assert_eq!(daily, (1.0, 3.0));
+    }
+
+    #[test]
+    fn replaces_existing_rows_and_their_rollups() {
+        let (result, imported, daily) = import(true);
+
+        assert_eq!((result.inserted, result.replaced, result.conflicts), (3, 1, 0));
+        assert_eq!(imported, [(3600, 1.0), (7200, 5.0), (10800, 3.0)]);
+        assert_eq!(daily, (1.0, 5.0));
+    }
+
+    #[test]
+    fn leaves_out_rows_in_archived_days() {
+        let (mut importer, path) = importer("archived", false);
+        // A day whose raw observations were deleted by the retention policy, leaving only its rollup
+        importer.conn.execute(
+            "INSERT INTO observation_daily (source, bucket, min, max, sum, count, weighted_sum, first, first_time, last, last_time)
+                VALUES ('east_west', 0, 1.0, 2.0, 3.0, 2, 0.0, 1.0, 3600, 2.0, 7200)",
+            [],
+        ).unwrap();
+
+        importer.insert_batch(&[row(3600, 1.0), row(50000, 10.0), row(86400, 4.0)]).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        let result = importer.finish().unwrap();
+        let imported = temperatures(&conn);
+        remove(&path);
+
+        assert_eq!((result.inserted, result.archived), (1, 2));
+        assert_eq!(imported, [(86400, 4.0)]);
+    }
+}
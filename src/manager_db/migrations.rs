@@ -0,0 +1,336 @@
+use log::info;
+use rusqlite::{Connection, Transaction};
+use crate::manager_db::errors::DBError;
+
+/// One step in the evolution of the database schema
+struct Migration {
+    version: i64,
+    description: &'static str,
+    up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// All migrations in the order they are to be applied, the version of the last one
+/// is the schema version this build of the application expects
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "create weather table", up: create_weather_table },
+    Migration { version: 2, description: "add precipitation to weather table", up: add_precipitation },
+    Migration { version: 3, description: "split weather table into observation and forecast tables", up: split_weather_table },
+    Migration { version: 4, description: "add hourly and daily observation rollups", up: create_rollups },
+    Migration { version: 5, description: "add time-weighted sums to observation rollups", up: add_weighted_sums },
+];
+
+/// Returns the schema version this build of the application expects
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Returns the current schema version of the database
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+pub fn schema_version(conn: &Connection) -> Result<i64, DBError> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Migrates the database to the latest schema version.
+/// Each migration runs in its own transaction together with the update of `user_version`,
+/// so a failing migration leaves the database at the last successfully applied version.
+///
+/// Returns the schema versions before and after migration
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+pub fn migrate(conn: &mut Connection) -> Result<(i64, i64), DBError> {
+    let from_version = schema_version(conn)?;
+    if from_version > latest_version() {
+        return Err(DBError::Other(format!(
+            "database schema version {} is newer than supported version {}", from_version, latest_version()
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from_version) {
+        info!("migrating database to version {}: {}", migration.version, migration.description);
+
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)
+            .map_err(|e| DBError::Other(format!("migration to version {} failed: {}", migration.version, e)))?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok((from_version, schema_version(conn)?))
+}
+
+/// Version 1, the original schema.
+/// Databases created before migrations were introduced already have the table but
+/// are at version 0, hence the table is only created if missing.
+fn create_weather_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS weather (
+            source text not null,
+            datetime integer not null,
+            temperature real null,
+            perceived_temperature real null,
+            humidity integer null,
+            wind_speed real null,
+            lcc_mean integer null,
+            mcc_mean integer null,
+            hcc_mean integer null,
+            symbol_code integer null,
+            constraint primary_key primary key (source, datetime)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Version 2, forecasted precipitation.
+/// The column may already exist in databases where it was added before migrations were introduced.
+fn add_precipitation(tx: &Transaction) -> rusqlite::Result<()> {
+    let has_precipitation: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('weather') WHERE name = 'precipitation'",
+        [],
+        |row| row.get(0),
+    )?;
+
+    if !has_precipitation {
+        tx.execute("ALTER TABLE weather ADD COLUMN precipitation real null", [])?;
+    }
+
+    Ok(())
+}
+
+/// Version 3, separate tables for observations and forecasts.
+/// A source is regarded as a forecast if any of its rows has forecast only columns set,
+/// and all rows of a source are moved to the same table.
+fn split_weather_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE observation (
+            source text not null,
+            datetime integer not null,
+            temperature real null,
+            perceived_temperature real null,
+            humidity integer null,
+            constraint observation_primary_key primary key (source, datetime)
+        );
+
+        CREATE TABLE forecast (
+            source text not null,
+            datetime integer not null,
+            temperature real null,
+            humidity integer null,
+            wind_speed real null,
+            lcc_mean integer null,
+            mcc_mean integer null,
+            hcc_mean integer null,
+            symbol_code integer null,
+            precipitation real null,
+            constraint forecast_primary_key primary key (source, datetime)
+        );
+
+        CREATE TEMPORARY TABLE forecast_source AS
+            SELECT DISTINCT source FROM weather
+            WHERE wind_speed IS NOT NULL OR lcc_mean IS NOT NULL OR mcc_mean IS NOT NULL
+                OR hcc_mean IS NOT NULL OR symbol_code IS NOT NULL OR precipitation IS NOT NULL;
+
+        INSERT INTO forecast (source, datetime, temperature, humidity, wind_speed, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation)
+            SELECT source, datetime, temperature, humidity, wind_speed, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation
+            FROM weather
+            WHERE source IN (SELECT source FROM forecast_source);
+
+        INSERT INTO observation (source, datetime, temperature, perceived_temperature, humidity)
+            SELECT source, datetime, temperature, perceived_temperature, humidity
+            FROM weather
+            WHERE source NOT IN (SELECT source FROM forecast_source);
+
+        DROP TABLE forecast_source;
+        DROP TABLE weather;"
+    )
+}
+
+/// Version 4, hourly and daily rollups of observed temperatures, backfilled from existing observations
+fn create_rollups(tx: &Transaction) -> rusqlite::Result<()> {
+    for (table, size) in [("observation_hourly", 3600), ("observation_daily", 86400)] {
+        tx.execute_batch(&format!(
+            "CREATE TABLE {table} (
+                source text not null,
+                bucket integer not null,
+                min real not null,
+                max real not null,
+                sum real not null,
+                count integer not null,
+                first real not null,
+                first_time integer not null,
+                last real not null,
+                last_time integer not null,
+                constraint {table}_primary_key primary key (source, bucket)
+            );
+
+            INSERT INTO {table} (source, bucket, min, max, sum, count, first, first_time, last, last_time)
+                SELECT source, bucket, MIN(temperature), MAX(temperature), SUM(temperature), COUNT(*),
+                    MIN(first), MIN(datetime), MIN(last), MAX(datetime)
+                FROM (
+                    SELECT source, datetime - datetime % {size} AS bucket, datetime, temperature,
+                        FIRST_VALUE(temperature) OVER w AS first,
+                        LAST_VALUE(temperature) OVER w AS last
+                    FROM observation
+                    WHERE temperature IS NOT NULL
+                    WINDOW w AS (
+                        PARTITION BY source, datetime - datetime % {size} ORDER BY datetime
+                        ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING
+                    )
+                )
+                GROUP BY source, bucket;"
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Version 5, sums of temperatures weighted by the seconds they held, for time-weighted means.
+/// They are backfilled for buckets whose observations are all still in the raw data, and left unknown
+/// for buckets that were archived.
+fn add_weighted_sums(tx: &Transaction) -> rusqlite::Result<()> {
+    for (table, size) in [("observation_hourly", 3600), ("observation_daily", 86400)] {
+        tx.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN weighted_sum real null;
+
+            UPDATE {table} SET weighted_sum = raw.weighted_sum
+                FROM (
+                    SELECT source, bucket, COALESCE(SUM(weighted), 0) AS weighted_sum, COUNT(*) AS count
+                    FROM (
+                        SELECT source, datetime - datetime % {size} AS bucket,
+                            temperature * (LEAD(datetime) OVER w - datetime) AS weighted
+                        FROM observation
+                        WHERE temperature IS NOT NULL
+                        WINDOW w AS (PARTITION BY source, datetime - datetime % {size} ORDER BY datetime)
+                    )
+                    GROUP BY source, bucket
+                ) AS raw
+                WHERE {table}.source = raw.source AND {table}.bucket = raw.bucket AND {table}.count = raw.count;"
+        ))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database as created by versions of the application before migrations were introduced
+    const V1_FIXTURE: &str = include_str!("../../tests/fixtures/weather_v1.sql");
+
+    fn v1_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V1_FIXTURE).unwrap();
+        conn
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table)).unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().map(|c| c.unwrap()).collect()
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrates_fresh_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let (from, to) = migrate(&mut conn).unwrap();
+
+        assert_eq!(from, 0);
+        assert_eq!(to, latest_version());
+        assert!(columns(&conn, "forecast").contains(&"precipitation".to_string()));
+        assert!(columns(&conn, "observation").contains(&"perceived_temperature".to_string()));
+        assert!(columns(&conn, "weather").is_empty());
+    }
+
+    #[test]
+    fn migrates_v1_fixture_forward() {
+        let mut conn = v1_database();
+        let rows_before = count(&conn, "SELECT COUNT(*) FROM weather");
+
+        let (from, to) = migrate(&mut conn).unwrap();
+
+        assert_eq!(from, 0);
+        assert_eq!(to, latest_version());
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        assert!(columns(&conn, "weather").is_empty());
+
+        let observations = count(&conn, "SELECT COUNT(*) FROM observation WHERE source = 'east_west'");
+        let forecasts = count(&conn, "SELECT COUNT(*) FROM forecast WHERE source = 'smhi'");
+        assert_eq!(observations, 3);
+        assert_eq!(forecasts, 3);
+        assert_eq!(rows_before, observations + forecasts);
+
+        let temp: f64 = conn.query_row(
+            "SELECT temperature FROM observation WHERE source = 'east_west' AND datetime = 1767225600", [], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(temp, -3.0);
+
+        let symbol_code: u8 = conn.query_row(
+            "SELECT symbol_code FROM forecast WHERE source = 'smhi' AND datetime = 1767232800", [], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(symbol_code, 3);
+    }
+
+    #[test]
+    fn backfills_rollups() {
+        let mut conn = v1_database();
+
+        migrate(&mut conn).unwrap();
+
+        // The fixture has observations at 00:00, 01:03 and 02:55 on 2026-01-01
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM observation_hourly WHERE source = 'east_west'"), 3);
+        let daily: (f64, f64, f64, i64, f64, f64) = conn.query_row(
+            "SELECT min, max, sum, count, first, last FROM observation_daily WHERE source = 'east_west' AND bucket = 1767225600",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)),
+        ).unwrap();
+        assert_eq!(daily, (-5.0, -3.0, -12.0, 3, -3.0, -5.0));
+        let weighted_sum: f64 = conn.query_row(
+            "SELECT weighted_sum FROM observation_daily WHERE source = 'east_west' AND bucket = 1767225600", [], |row| row.get(0),
+        ).unwrap();
+        assert_eq!(weighted_sum, -3.0 * 3780.0 + -4.0 * 6720.0);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM observation_daily WHERE source = 'smhi'"), 0);
+    }
+
+    #[test]
+    fn allows_same_source_name_for_observation_and_forecast() {
+        let mut conn = v1_database();
+
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO observation (source, datetime, temperature) VALUES ('smhi', 1767225600, 1.0)", [],
+        ).unwrap();
+
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM observation WHERE source = 'smhi'"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM forecast WHERE source = 'smhi'"), 3);
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let mut conn = v1_database();
+
+        migrate(&mut conn).unwrap();
+        let (from, to) = migrate(&mut conn).unwrap();
+
+        assert_eq!(from, latest_version());
+        assert_eq!(to, latest_version());
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+
+        assert!(migrate(&mut conn).is_err());
+    }
+}
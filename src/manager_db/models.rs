@@ -1,5 +1,8 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::Serialize;
+use crate::manager_db::rollups::Resolution;
+use crate::statistics::Stat;
+use crate::weather_symbols::SymbolInfo;
 
 #[derive(Serialize)]
 pub struct DataItem<T> {
@@ -7,13 +10,23 @@ pub struct DataItem<T> {
     pub y: T,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Debug)]
 pub struct MinMax {
     pub min: f64,
+    pub min_time: Option<DateTime<Utc>>,
     pub max: f64,
+    pub max_time: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize)]
+/// Min/max temperature of a local calendar period starting at the given date
+#[derive(Serialize, Clone, Debug)]
+pub struct MinMaxPeriod {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub min_max: MinMax,
+}
+
+#[derive(Serialize, Clone, Debug)]
 pub struct ForecastRecord {
     pub date_time: DateTime<Utc>,
     pub temperature: Option<f64>,
@@ -23,6 +36,45 @@ pub struct ForecastRecord {
     pub mcc_mean: Option<u8>,
     pub hcc_mean: Option<u8>,
     pub symbol_code: Option<u8>,
+    pub precipitation: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<SymbolInfo>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ForecastSummary {
+    pub date_time: DateTime<Utc>,
+    pub min_temperature: Option<f64>,
+    pub max_temperature: Option<f64>,
+    pub max_wind_speed: Option<f64>,
+    pub total_precipitation: Option<f64>,
+    pub mean_humidity: Option<f64>,
+    pub mean_lcc: Option<f64>,
+    pub mean_mcc: Option<f64>,
+    pub mean_hcc: Option<f64>,
+    pub symbol_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<SymbolInfo>,
+}
+
+/// Forecast as stored or interpolated onto a time grid, or aggregated per time step
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Forecast {
+    Records(Vec<ForecastRecord>),
+    Summaries(Vec<ForecastSummary>),
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DailyForecast {
+    pub date: NaiveDate,
+    pub min_temperature: Option<f64>,
+    pub max_temperature: Option<f64>,
+    pub total_precipitation: Option<f64>,
+    pub max_wind_speed: Option<f64>,
+    pub symbol_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<SymbolInfo>,
 }
 
 #[derive(Serialize)]
@@ -30,4 +82,105 @@ pub struct Temperature {
     pub history: Vec<DataItem<f64>>,
     pub current_temp: Option<f64>,
     pub perceived_temp: Option<f64>,
-}
\ No newline at end of file
+    pub resolution: Resolution,
+}
+
+/// Stored observation of a source
+#[derive(Serialize, Clone, Debug)]
+pub struct Observation {
+    pub source: String,
+    pub date_time: DateTime<Utc>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<u8>,
+    pub perceived_temperature: Option<f64>,
+}
+
+/// Direction of the temperature over the last hour
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+}
+
+/// Snapshot of the current conditions at an observation source
+#[derive(Serialize, Clone, Debug)]
+pub struct CurrentConditions {
+    pub source: String,
+    pub date_time: DateTime<Utc>,
+    pub age_secs: i64,
+    pub temperature: Option<f64>,
+    pub humidity: Option<u8>,
+    pub perceived_temperature: Option<f64>,
+    pub trend: Option<Trend>,
+    pub today: Option<MinMax>,
+    pub forecast: Option<ForecastRecord>,
+}
+
+
+/// Kind of data stored for a source
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Observation,
+    Forecast,
+}
+
+/// What is stored for a source, where the first timestamp of an observation source includes the rollups
+/// and the row count only the raw records
+#[derive(Serialize, Clone, Debug)]
+pub struct SourceStats {
+    pub source: String,
+    pub kind: SourceKind,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub count: i64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Location {
+    pub lat: f64,
+    pub long: f64,
+}
+
+/// A known source, configured and/or found in the database
+#[derive(Serialize, Clone, Debug)]
+pub struct Source {
+    pub id: String,
+    pub kind: SourceKind,
+    pub name: Option<String>,
+    pub configured: bool,
+    pub location: Option<Location>,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub count: i64,
+}
+
+
+/// Statistics of the temperatures within one bucket, only the requested ones are set
+#[derive(Serialize, Clone, Debug)]
+pub struct TemperatureStats {
+    pub x: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p10: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct BucketedTemperature {
+    pub buckets: Vec<TemperatureStats>,
+    pub stats: Vec<Stat>,
+    pub bucket_secs: Option<i64>,
+    pub current_temp: Option<f64>,
+    pub perceived_temp: Option<f64>,
+}
@@ -0,0 +1,127 @@
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use rusqlite::{Connection, OpenFlags};
+use crate::manager_db::errors::DBError;
+
+/// Time a connection waits for a lock held by another connection before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time a request waits for a pooled connection before giving up
+pub const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pool of SQLite connections to a database in WAL mode.
+///
+/// SQLite allows only one writer at a time, so all writes go through one dedicated connection,
+/// while reads are spread over a number of read-only connections. In WAL mode readers don't block
+/// the writer and vice versa, so a slow read never delays an insert.
+pub struct Pool {
+    writer: Mutex<Connection>,
+    readers: Mutex<Vec<Connection>>,
+    reader_returned: Condvar,
+}
+
+impl Pool {
+    /// Opens the writer connection, which is handed to the given setup function (e.g. for migrations)
+    /// before the read-only connections are opened
+    ///
+    /// # Arguments
+    ///
+    /// * 'db_path' - full path to db file
+    /// * 'readers' - number of read-only connections
+    /// * 'setup' - function run on the writer connection before readers are opened
+    pub fn new(db_path: &str, readers: usize, setup: impl FnOnce(&mut Connection) -> Result<(), DBError>) -> Result<Self, DBError> {
+        let mut writer = Connection::open(db_path)?;
+        writer.busy_timeout(BUSY_TIMEOUT)?;
+        let mode: String = writer.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            return Err(DBError::Other(format!("failed to set WAL journal mode, got: {}", mode)));
+        }
+        writer.pragma_update(None, "synchronous", "NORMAL")?;
+
+        setup(&mut writer)?;
+
+        let readers = (0..readers.max(1))
+            .map(|_| {
+                let conn = Connection::open_with_flags(
+                    db_path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+                )?;
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                Ok(conn)
+            })
+            .collect::<Result<Vec<Connection>, DBError>>()?;
+
+        Ok(Pool {
+            writer: Mutex::new(writer),
+            readers: Mutex::new(readers),
+            reader_returned: Condvar::new(),
+        })
+    }
+
+    /// Runs the given function with exclusive access to the writer connection.
+    /// This blocks the current thread, so call it from a blocking context.
+    ///
+    /// # Arguments
+    ///
+    /// * 'f' - function to run
+    pub fn write<T>(&self, f: impl FnOnce(&mut Connection) -> Result<T, DBError>) -> Result<T, DBError> {
+        let mut conn = self.writer.lock().map_err(|e| DBError::Other(e.to_string()))?;
+
+        f(&mut conn)
+    }
+
+    /// Runs the given function with one of the read-only connections, waiting up to `ACQUIRE_TIMEOUT`
+    /// for one to become available if all are in use.
+    /// This blocks the current thread, so call it from a blocking context.
+    ///
+    /// # Arguments
+    ///
+    /// * 'f' - function to run
+    pub fn read<T>(&self, f: impl FnOnce(&Connection) -> Result<T, DBError>) -> Result<T, DBError> {
+        let conn = self.get_reader()?;
+
+        f(&conn)
+    }
+
+    /// Takes a reader out of the pool, it is returned when dropped
+    fn get_reader(&self) -> Result<PooledReader<'_>, DBError> {
+        let deadline = Instant::now() + ACQUIRE_TIMEOUT;
+        let mut readers = self.readers.lock().map_err(|e| DBError::Other(e.to_string()))?;
+        loop {
+            if let Some(conn) = readers.pop() {
+                return Ok(PooledReader { pool: self, conn: Some(conn) });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DBError::PoolTimeout);
+            }
+            readers = self.reader_returned.wait_timeout(readers, remaining).map_err(|e| DBError::Other(e.to_string()))?.0;
+        }
+    }
+}
+
+/// A read-only connection borrowed from the pool
+struct PooledReader<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledReader<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledReader<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if let Ok(mut readers) = self.pool.readers.lock() {
+                readers.push(conn);
+                self.pool.reader_returned.notify_one();
+            }
+        }
+    }
+}
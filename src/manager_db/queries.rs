@@ -0,0 +1,507 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use crate::manager_db::errors::DBError;
+use crate::manager_db::models::{DataItem, ForecastRecord, MinMax, Observation, SourceKind, SourceStats, Temperature};
+use crate::manager_db::rollups::{add_to_rollups, time_weighted_means, BucketAggregate, Resolution, BUCKET_AGGREGATE_COLUMNS};
+use crate::interpolation::{interpolate, MAX_INTERPOLATION_DISTANCE};
+
+/// Queries and statements run on one database connection
+pub struct Queries<'a> {
+    pub db_conn: &'a Connection,
+}
+
+impl Queries<'_> {
+
+    /// Inserts an observation record in the database and adds it to the hourly and daily rollups
+    /// 
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)    
+    /// * 'date_time' - time of the observation
+    /// * 'temp' - temperature
+    /// * 'humidity' - humidity
+    /// * 'perceived_temp' - perceived temperature
+    pub fn insert_observation_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        humidity: Option<u8>,
+        perceived_temp: Option<f64>,
+    ) -> Result<(), DBError> {
+        let timestamp = date_time.timestamp();
+
+        let tx = self.db_conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO observation (source, datetime, temperature, humidity, perceived_temperature) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![source, timestamp, temp, humidity, perceived_temp],
+        )?;
+        add_to_rollups(&tx, source, timestamp, temp)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Inserts (or updates) a forecast record in the database.
+    /// The main difference is that these records often tend to update existing records
+    /// when newer forecasts replace older ones.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'date_time' - forecast date and time in UTC
+    /// * 'temp' - temperature
+    /// * 'wind_speed' - wind speed
+    /// * 'humidity' - humidity
+    /// * 'lcc_mean' - low level cloud index
+    /// * 'mcc_mean' - medium level cloud index
+    /// * 'hcc_mean' - high level cloud index
+    /// * 'symbol_code' - weather symbol code
+    /// * 'precipitation' - mean precipitation intensity in mm/h
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_forecast_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        wind_speed: Option<f64>,
+        humidity: Option<u8>,
+        lcc_mean: Option<u8>,
+        mcc_mean: Option<u8>,
+        hcc_mean: Option<u8>,
+        symbol_code: Option<u8>,
+        precipitation: Option<f64>,
+    ) -> Result<(), DBError> {
+
+        self.db_conn.execute(
+            "INSERT INTO forecast (source, datetime, temperature, humidity, wind_speed, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    ON CONFLICT (source, datetime) DO UPDATE SET temperature = ?3, humidity = ?4, wind_speed = ?5, lcc_mean = ?6, mcc_mean = ?7, hcc_mean = ?8, symbol_code = ?9, precipitation = ?10",
+            params![source, date_time.timestamp(), temp, humidity, wind_speed, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the temperature history between (non-inclusive) given boundaries together with the
+    /// current temperature, see `Storage::temperature_history`
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from_datetime' - start of the range
+    /// * 'to_datetime' - end of the range (non-inclusive)
+    /// * 'resolution' - resolution of the history
+    pub fn temperature_history(
+        &self,
+        source: &str,
+        from_datetime: DateTime<Utc>,
+        to_datetime: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Temperature, DBError> {
+        let from_timestamp = from_datetime.timestamp();
+        let to_timestamp = to_datetime.timestamp();
+
+        let mut result = Temperature {
+            history: Vec::new(),
+            current_temp: None,
+            perceived_temp: None,
+            resolution,
+        };
+
+        if let Some((table, size)) = resolution.table() {
+            let first_bucket = from_timestamp - from_timestamp.rem_euclid(size);
+            let mut stmt = self.db_conn.prepare(&format!(
+                "SELECT {} FROM {}
+                    WHERE source = ?1 AND bucket >= ?2 AND bucket < ?3
+                    ORDER BY bucket ASC;",
+                BUCKET_AGGREGATE_COLUMNS, table,
+            ))?;
+            let buckets = stmt
+                .query_map(params![source, first_bucket, to_timestamp], BucketAggregate::from_row)?
+                .collect::<rusqlite::Result<Vec<BucketAggregate>>>()?;
+
+            // The temperature held when the first bucket starts
+            let held: Option<f64> = self.db_conn.query_row(
+                &format!("SELECT last FROM {} WHERE source = ?1 AND bucket < ?2 ORDER BY bucket DESC LIMIT 1;", table),
+                params![source, first_bucket],
+                |row| row.get(0),
+            ).optional()?;
+
+            result.history = time_weighted_means(&buckets, size, held, Utc::now().timestamp());
+
+            // The current temperature is the last recorded one, not the last hourly or daily mean
+            result.current_temp = self.db_conn.query_row(
+                "SELECT temperature FROM observation
+                    WHERE source = ?1 AND datetime < ?2 AND temperature IS NOT NULL
+                    ORDER BY datetime DESC LIMIT 1;",
+                params![source, to_timestamp],
+                |row| row.get(0),
+            ).optional()?;
+
+            if let (true, Some(y)) = (result.history.is_empty(), result.current_temp) {
+                result.history.push(DataItem { x: from_datetime, y });
+            }
+        } else {
+            self.get_raw_temp_history(source, from_timestamp, to_timestamp, &mut result)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Adds every recorded temperature between (non-inclusive) given boundaries to the given result,
+    /// including the last known temperature before the range which is given the `from` time
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from_timestamp' - start of the range
+    /// * 'to_timestamp' - end of the range (non-inclusive)
+    /// * 'result' - result to add history and current temperature to
+    fn get_raw_temp_history(&self, source: &str, from_timestamp: i64, to_timestamp: i64, result: &mut Temperature) -> Result<(), DBError> {
+        // Combine the range query and the "last known value" query.
+        // For the last known value, we force the datetime to be the 'from' timestamp (?2)
+        let mut stmt = self.db_conn.prepare(
+            "SELECT datetime, temperature FROM (
+                SELECT datetime, temperature FROM observation
+                WHERE source = ?1 AND datetime > ?2 AND datetime < ?3 AND temperature IS NOT NULL
+                UNION ALL
+                SELECT ?2 as datetime, temperature FROM (
+                    SELECT temperature, datetime FROM observation
+                    WHERE source = ?1 AND datetime <= ?2 AND temperature IS NOT NULL
+                    ORDER BY datetime DESC LIMIT 1
+                )
+            ) ORDER BY datetime ASC;",
+        )?;
+
+        let mut rows = stmt.query(params![source, from_timestamp, to_timestamp])?;
+
+        while let Some(row) = rows.next()? {
+            let timestamp: i64 = row.get(0)?;
+            let x = DateTime::from_timestamp(timestamp, 0).unwrap();
+            let y: f64 = row.get(1)?;
+            result.current_temp = Some(y);
+            result.history.push(DataItem { x, y });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the rollup buckets between given boundaries, see `Storage::rollup_buckets`
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range, the bucket containing it is included
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'resolution' - hourly or daily rollups, none for raw resolution
+    pub fn rollup_buckets(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Vec<BucketAggregate>, DBError> {
+        let Some((table, size)) = resolution.table() else {
+            return Ok(Vec::new());
+        };
+
+        let from_timestamp = from.timestamp();
+        let mut stmt = self.db_conn.prepare(&format!(
+            "SELECT {} FROM {}
+                WHERE source = ?1 AND bucket >= ?2 AND bucket < ?3
+                ORDER BY bucket ASC;",
+            BUCKET_AGGREGATE_COLUMNS, table,
+        ))?;
+
+        let buckets = stmt
+            .query_map(
+                params![source, from_timestamp - from_timestamp.rem_euclid(size), to.timestamp()],
+                BucketAggregate::from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<BucketAggregate>>>()?;
+
+        Ok(buckets)
+    }
+
+    /// Returns whatever forecast records are recorded between given boundaries
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    pub fn get_forecast_records(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, DBError> {
+        let mut result: Vec<ForecastRecord> = Vec::new();
+
+        // Get what may naturally be between the given time boundary from the database
+        let mut stmt = self.db_conn.prepare(
+            "SELECT datetime, temperature, wind_speed, humidity, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation
+                FROM forecast
+                WHERE source = ?1 AND datetime >= ?2 AND datetime < ?3
+                ORDER BY datetime;",
+        )?;
+        let mut rows = stmt.query(params![source, from.timestamp(), to.timestamp()])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(forecast_record_from_row(row)?);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns a forecast record interpolated between the stored records closest before and after
+    /// the given time. If there is a stored record at exactly the given time, it is returned as is.
+    ///
+    /// Records further away than `MAX_INTERPOLATION_DISTANCE` are not considered, so None is returned
+    /// for times outside what the stored forecast covers.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'date_time' - datetime to get a forecast for
+    pub fn interpolated_forecast(&self, source: &str, date_time: DateTime<Utc>) -> Result<Option<ForecastRecord>, DBError> {
+        let timestamp = date_time.timestamp();
+        let distance = MAX_INTERPOLATION_DISTANCE.num_seconds();
+
+        let mut stmt = self.db_conn.prepare(
+            "SELECT datetime, temperature, wind_speed, humidity, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation
+                FROM forecast
+                WHERE source = ?1 AND datetime <= ?2 AND datetime >= ?3
+                ORDER BY datetime DESC LIMIT 1;",
+        )?;
+        let before = stmt.query_row(params![source, timestamp, timestamp - distance], forecast_record_from_row).optional()?;
+
+        let mut stmt = self.db_conn.prepare(
+            "SELECT datetime, temperature, wind_speed, humidity, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation
+                FROM forecast
+                WHERE source = ?1 AND datetime >= ?2 AND datetime <= ?3
+                ORDER BY datetime ASC LIMIT 1;",
+        )?;
+        let after = stmt.query_row(params![source, timestamp, timestamp + distance], forecast_record_from_row).optional()?;
+
+        match (before, after) {
+            (Some(before), _) if before.date_time == date_time => Ok(Some(before)),
+            (Some(before), Some(after)) => Ok(Some(interpolate(&before, &after, date_time))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns min/max temperature values, see `Storage::min_max`
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'start' - start of the range
+    /// * 'end' - end of the range (non-inclusive)
+    pub fn min_max(&self, source: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Option<MinMax>, DBError> {
+        // The extra subqueries are needed for sqlite 'order by' having an explicit column when used in a 'union all'.
+        // The last known temperature before the range is given the start time. Days before the oldest raw
+        // observation have been archived by the retention policy, and for those the hourly and daily rollups lying
+        // fully within the range are used instead, without the time of the extremes. The earliest time is returned
+        // for extremes occurring several times.
+        let mut stmt = self.db_conn.prepare(
+            "WITH archived (until) AS (
+                SELECT COALESCE(MIN(datetime) - MIN(datetime) % 86400, ?3) FROM observation WHERE source = ?1
+             ),
+             candidate (min_temp, max_temp, datetime) AS (
+                SELECT temperature, temperature, datetime FROM observation
+                WHERE source = ?1 AND datetime > ?2 AND datetime < ?3
+                UNION ALL
+                SELECT temperature, temperature, ?2 FROM (
+                    SELECT temperature, datetime FROM observation
+                    WHERE source = ?1 AND datetime <= ?2
+                    ORDER BY datetime DESC LIMIT 1
+                )
+                UNION ALL
+                SELECT min, max, NULL FROM observation_hourly
+                WHERE source = ?1 AND bucket >= ?2 AND bucket + 3600 <= ?3 AND bucket + 3600 <= (SELECT until FROM archived)
+                UNION ALL
+                SELECT min, max, NULL FROM observation_daily
+                WHERE source = ?1 AND bucket >= ?2 AND bucket + 86400 <= ?3 AND bucket + 86400 <= (SELECT until FROM archived)
+             )
+             SELECT temp, datetime FROM (
+                SELECT min_temp AS temp, datetime FROM candidate WHERE min_temp IS NOT NULL
+                ORDER BY min_temp ASC, datetime IS NULL, datetime ASC LIMIT 1
+             )
+             UNION ALL
+             SELECT temp, datetime FROM (
+                SELECT max_temp AS temp, datetime FROM candidate WHERE max_temp IS NOT NULL
+                ORDER BY max_temp DESC, datetime IS NULL, datetime ASC LIMIT 1
+             );",
+        )?;
+
+        let extremes = stmt.query_map(params![source, start.timestamp(), end.timestamp()], |row| {
+            let timestamp: Option<i64> = row.get(1)?;
+            Ok((row.get::<_, f64>(0)?, timestamp.and_then(|t| DateTime::from_timestamp(t, 0))))
+        })?.collect::<rusqlite::Result<Vec<(f64, Option<DateTime<Utc>>)>>>()?;
+
+        match extremes.as_slice() {
+            [(min, min_time), (max, max_time)] => Ok(Some(MinMax { min: *min, min_time: *min_time, max: *max, max_time: *max_time })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the latest observation of each source ordered by source, or of the given source only
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source), all sources if None
+    pub fn latest_observations(&self, source: Option<&str>) -> Result<Vec<Observation>, DBError> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT source, datetime, temperature, humidity, perceived_temperature FROM observation o
+                WHERE (?1 IS NULL OR source = ?1)
+                    AND datetime = (SELECT MAX(datetime) FROM observation WHERE source = o.source)
+                ORDER BY source;",
+        )?;
+
+        let rows = stmt.query_map(params![source], |row| {
+            let timestamp: i64 = row.get(1)?;
+
+            Ok(Observation {
+                source: row.get(0)?,
+                date_time: DateTime::from_timestamp(timestamp, 0).unwrap(),
+                temperature: row.get(2)?,
+                humidity: row.get(3)?,
+                perceived_temperature: row.get(4)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<Vec<Observation>>>()?)
+    }
+
+    /// Returns what is stored for each source, see `Storage::source_stats`
+    ///
+    pub fn source_stats(&self) -> Result<Vec<SourceStats>, DBError> {
+        // Archived days are only in the rollups, so they count when finding the first timestamp
+        let mut stmt = self.db_conn.prepare(
+            "SELECT source, 'observation', MIN(first), MAX(last), SUM(count) FROM (
+                SELECT source, MIN(datetime) AS first, MAX(datetime) AS last, COUNT(*) AS count FROM observation GROUP BY source
+                UNION ALL
+                SELECT source, MIN(first_time), MAX(last_time), 0 FROM observation_daily GROUP BY source
+             ) GROUP BY source
+             UNION ALL
+             SELECT source, 'forecast', MIN(datetime), MAX(datetime), COUNT(*) FROM forecast GROUP BY source
+             ORDER BY 1, 2;",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let kind: String = row.get(1)?;
+            let first: Option<i64> = row.get(2)?;
+            let last: Option<i64> = row.get(3)?;
+
+            Ok(SourceStats {
+                source: row.get(0)?,
+                kind: if kind == "forecast" { SourceKind::Forecast } else { SourceKind::Observation },
+                first: first.and_then(|t| DateTime::from_timestamp(t, 0)),
+                last: last.and_then(|t| DateTime::from_timestamp(t, 0)),
+                count: row.get(4)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<Vec<SourceStats>>>()?)
+    }
+
+    /// Returns true if anything is stored for the given source
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source to look for
+    pub fn has_source(&self, source: &str) -> Result<bool, DBError> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT EXISTS (SELECT 1 FROM observation WHERE source = ?1)
+                OR EXISTS (SELECT 1 FROM forecast WHERE source = ?1)
+                OR EXISTS (SELECT 1 FROM observation_daily WHERE source = ?1);",
+        )?;
+
+        Ok(stmt.query_row(params![source], |row| row.get(0))?)
+    }
+}
+
+/// Maps a row with forecast columns (datetime, temperature, wind_speed, humidity, lcc_mean, mcc_mean,
+/// hcc_mean, symbol_code, precipitation) to a forecast record
+///
+/// # Arguments
+///
+/// * 'row' - the row to map
+fn forecast_record_from_row(row: &Row) -> rusqlite::Result<ForecastRecord> {
+    let timestamp: i64 = row.get(0)?;
+
+    Ok(ForecastRecord {
+        date_time: DateTime::from_timestamp(timestamp, 0).unwrap(),
+        temperature: row.get(1)?,
+        wind_speed: row.get(2)?,
+        humidity: row.get(3)?,
+        lcc_mean: row.get(4)?,
+        mcc_mean: row.get(5)?,
+        hcc_mean: row.get(6)?,
+        symbol_code: row.get(7)?,
+        precipitation: row.get(8)?,
+        symbol: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager_db::migrations;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn insert_observation(conn: &Connection, timestamp: i64, temp: Option<f64>) {
+        conn.execute(
+            "INSERT INTO observation (source, datetime, temperature) VALUES ('east_west', ?1, ?2)", params![timestamp, temp],
+        ).unwrap();
+    }
+
+    fn time(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn raw_history_leaves_out_missing_temperatures() {
+        let conn = database();
+        insert_observation(&conn, 100, Some(5.0));
+        insert_observation(&conn, 200, None);
+        insert_observation(&conn, 300, Some(6.0));
+        insert_observation(&conn, 400, None);
+
+        let queries = Queries { db_conn: &conn };
+        let raw = queries.temperature_history("east_west", time(250), time(500), Resolution::Raw).unwrap();
+
+        let history = raw.history.iter().map(|d| (d.x.timestamp(), d.y)).collect::<Vec<(i64, f64)>>();
+        assert_eq!(history, [(250, 5.0), (300, 6.0)]);
+        assert_eq!(raw.current_temp, Some(6.0));
+
+        let hourly = queries.temperature_history("east_west", time(0), time(500), Resolution::Hourly).unwrap();
+        assert_eq!(hourly.current_temp, Some(6.0));
+    }
+
+    #[test]
+    fn interpolates_stored_forecast() {
+        let conn = database();
+        let queries = Queries { db_conn: &conn };
+        let hour = |h: i64| time(h * 3600);
+        queries.insert_forecast_record("smhi", hour(0), 2.0, Some(4.0), None, None, None, None, Some(1), None).unwrap();
+        queries.insert_forecast_record("smhi", hour(2), 4.0, None, None, None, None, None, Some(3), Some(1.0)).unwrap();
+        queries.insert_forecast_record("smhi", hour(15), 6.0, None, None, None, None, None, None, None).unwrap();
+        queries.insert_forecast_record("other", hour(1), 20.0, None, None, None, None, None, None, None).unwrap();
+
+        let exact = queries.interpolated_forecast("smhi", hour(2)).unwrap().unwrap();
+        assert_eq!((exact.temperature, exact.symbol_code, exact.precipitation), (Some(4.0), Some(3), Some(1.0)));
+
+        let between = queries.interpolated_forecast("smhi", hour(1)).unwrap().unwrap();
+        assert_eq!(between.date_time, hour(1));
+        assert_eq!((between.temperature, between.wind_speed, between.precipitation), (Some(3.0), Some(4.0), Some(1.0)));
+
+        // Before the first record, after the last one and across a gap of more than 6 hours
+        assert!(queries.interpolated_forecast("smhi", hour(-1)).unwrap().is_none());
+        assert!(queries.interpolated_forecast("smhi", hour(16)).unwrap().is_none());
+        assert!(queries.interpolated_forecast("smhi", hour(8)).unwrap().is_none());
+        assert!(queries.interpolated_forecast("unknown", hour(1)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{TimeDelta, Utc};
+use log::info;
+use rusqlite::{params, Connection};
+use crate::manager_db::errors::DBError;
+use crate::manager_db::rollups::{rebuild_rollups, Resolution};
+
+/// How long data is kept in the database, in days
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    pub observation_days: i64,
+    pub forecast_days: i64,
+    pub hourly_days: Option<i64>,
+    pub daily_days: Option<i64>,
+}
+
+impl RetentionPolicy {
+    /// Returns the max age of the hourly and daily rollups, if any. Hourly rollups are kept at least as long as the
+    /// daily ones, since daily rollups are of UTC days and local days of archived observations need the hourly ones.
+    pub fn rollup_days(&self) -> [Option<i64>; 2] {
+        let hourly_days = self.daily_days.and_then(|daily| self.hourly_days.map(|hourly| hourly.max(daily)));
+
+        [hourly_days, self.daily_days]
+    }
+}
+
+/// Applies the retention policy.
+///
+/// Raw observations are only deleted in whole (UTC) days, and before deletion the hourly and daily
+/// rollups for those days are rebuilt from the raw data. That way the rollups are complete even for
+/// observations that for some reason never made it into the rollups when inserted, and long-term
+/// min/max and averages remain available after the raw data is gone.
+///
+/// Rollups are kept indefinitely unless a max age is given for them, see `RetentionPolicy::rollup_days`.
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+/// * 'policy' - the retention policy to apply
+pub fn apply_retention(conn: &Connection, policy: &RetentionPolicy) -> Result<(), DBError> {
+    let now = Utc::now().timestamp();
+    let day = TimeDelta::days(1).num_seconds();
+    let cutoff = |days: i64| now - TimeDelta::days(days).num_seconds();
+
+    let observation_cutoff = cutoff(policy.observation_days);
+    let observation_cutoff = observation_cutoff - observation_cutoff.rem_euclid(day);
+
+    let tx = conn.unchecked_transaction()?;
+
+    rebuild_rollups(&tx, None, i64::MIN, observation_cutoff)?;
+
+    let observations = tx.execute("DELETE FROM observation WHERE datetime < ?1", params![observation_cutoff])?;
+    let forecasts = tx.execute("DELETE FROM forecast WHERE datetime < ?1", params![cutoff(policy.forecast_days)])?;
+
+    let mut rollups = 0;
+    for (resolution, days) in [Resolution::Hourly, Resolution::Daily].into_iter().zip(policy.rollup_days()) {
+        if let (Some((table, _)), Some(days)) = (resolution.table(), days) {
+            rollups += tx.execute(&format!("DELETE FROM {} WHERE bucket < ?1", table), params![cutoff(days)])?;
+        }
+    }
+
+    tx.commit()?;
+
+    info!("retention applied, deleted {} observations, {} forecasts and {} rollups", observations, forecasts, rollups);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(hourly_days: Option<i64>, daily_days: Option<i64>) -> RetentionPolicy {
+        RetentionPolicy { observation_days: 30, forecast_days: 30, hourly_days, daily_days }
+    }
+
+    #[test]
+    fn keeps_hourly_rollups_as_long_as_daily_ones() {
+        assert_eq!(policy(Some(100), None).rollup_days(), [None, None]);
+        assert_eq!(policy(Some(100), Some(365)).rollup_days(), [Some(365), Some(365)]);
+        assert_eq!(policy(Some(400), Some(365)).rollup_days(), [Some(400), Some(365)]);
+        assert_eq!(policy(None, Some(365)).rollup_days(), [None, Some(365)]);
+    }
+}
@@ -0,0 +1,341 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use crate::manager_db::models::DataItem;
+
+/// Ranges up to this length are returned in raw resolution
+const MAX_RAW_RANGE: TimeDelta = TimeDelta::days(7);
+
+/// Ranges up to this length (but longer than `MAX_RAW_RANGE`) are returned in hourly resolution,
+/// longer ranges in daily resolution
+const MAX_HOURLY_RANGE: TimeDelta = TimeDelta::days(90);
+
+/// Resolution of observation history
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// Every recorded change
+    Raw,
+    /// One aggregate per hour
+    Hourly,
+    /// One aggregate per day (UTC)
+    Daily,
+}
+
+impl Resolution {
+    /// Returns a suitable resolution for the given range, so that long ranges don't return
+    /// an excessive number of rows
+    ///
+    /// # Arguments
+    ///
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range
+    pub fn for_range(from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        let range = to - from;
+
+        if range <= MAX_RAW_RANGE {
+            Resolution::Raw
+        } else if range <= MAX_HOURLY_RANGE {
+            Resolution::Hourly
+        } else {
+            Resolution::Daily
+        }
+    }
+
+    /// Returns the rollup table and its bucket size in seconds, None for raw resolution
+    pub fn table(&self) -> Option<(&'static str, i64)> {
+        match self {
+            Resolution::Raw => None,
+            Resolution::Hourly => Some(("observation_hourly", 3600)),
+            Resolution::Daily => Some(("observation_daily", 86400)),
+        }
+    }
+}
+
+/// Columns of a rollup table read into a `BucketAggregate`, in order
+pub const BUCKET_AGGREGATE_COLUMNS: &str = "bucket, sum, count, weighted_sum, first_time, last, last_time, min, max";
+
+/// Aggregate of one rollup bucket, as needed for its statistics
+#[derive(Clone, Copy, Debug)]
+pub struct BucketAggregate {
+    /// Unix timestamp of the start of the bucket
+    pub bucket: i64,
+    pub sum: f64,
+    pub count: i64,
+    /// Sum of each temperature multiplied by the seconds it held until the next one within the bucket,
+    /// None if unknown (rollups archived before it was introduced)
+    pub weighted_sum: Option<f64>,
+    pub first_time: i64,
+    pub last: f64,
+    pub last_time: i64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl BucketAggregate {
+    /// Reads an aggregate from a row of `BUCKET_AGGREGATE_COLUMNS`
+    ///
+    /// # Arguments
+    ///
+    /// * 'row' - the row to read
+    pub fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(BucketAggregate {
+            bucket: row.get(0)?,
+            sum: row.get(1)?,
+            count: row.get(2)?,
+            weighted_sum: row.get(3)?,
+            first_time: row.get(4)?,
+            last: row.get(5)?,
+            last_time: row.get(6)?,
+            min: row.get(7)?,
+            max: row.get(8)?,
+        })
+    }
+
+    /// Returns the time-weighted mean of the bucket, where each temperature holds until the next one.
+    /// The time before the first temperature of the bucket is held by the last temperature before the bucket,
+    /// and the last temperature holds until the end of the bucket (or now, if earlier).
+    /// Falls back to the plain mean of the temperatures if the weighted sum is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * 'size' - bucket size in seconds
+    /// * 'held' - the last temperature before the bucket, if any
+    /// * 'now' - unix timestamp of now
+    pub fn mean(&self, size: i64, held: Option<f64>, now: i64) -> f64 {
+        let plain_mean = self.sum / self.count as f64;
+        let Some(weighted_sum) = self.weighted_sum else {
+            return plain_mean;
+        };
+
+        let end = (self.bucket + size).min(now).max(self.last_time);
+        let mut total = weighted_sum + self.last * (end - self.last_time) as f64;
+        let mut seconds = end - self.first_time;
+        if let Some(held) = held {
+            total += held * (self.first_time - self.bucket) as f64;
+            seconds += self.first_time - self.bucket;
+        }
+
+        if seconds > 0 { total / seconds as f64 } else { plain_mean }
+    }
+}
+
+/// Returns the time-weighted means of consecutive rollup buckets, see `BucketAggregate::mean`
+///
+/// # Arguments
+///
+/// * 'buckets' - the buckets in ascending order
+/// * 'size' - bucket size in seconds
+/// * 'held' - the last temperature before the first bucket, if any
+/// * 'now' - unix timestamp of now
+pub fn time_weighted_means(buckets: &[BucketAggregate], size: i64, mut held: Option<f64>, now: i64) -> Vec<DataItem<f64>> {
+    buckets.iter()
+        .map(|b| {
+            let y = b.mean(size, held, now);
+            held = Some(b.last);
+
+            DataItem { x: DateTime::from_timestamp(b.bucket, 0).unwrap(), y }
+        })
+        .collect()
+}
+
+/// Adds an observation to the hourly and daily rollups.
+///
+/// The weighted sum is extended when the observation is before or after all others of the bucket,
+/// an observation in between would need its neighbours, so the weighted sum becomes unknown until
+/// the bucket is rebuilt.
+///
+/// # Arguments
+///
+/// * 'conn' - database connection, preferably within the transaction inserting the observation
+/// * 'source' - sensor id (source)
+/// * 'timestamp' - unix timestamp of the observation
+/// * 'temp' - temperature
+pub fn add_to_rollups(conn: &Connection, source: &str, timestamp: i64, temp: f64) -> rusqlite::Result<()> {
+    for (table, size) in [Resolution::Hourly, Resolution::Daily].iter().filter_map(|r| r.table()) {
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (source, bucket, min, max, sum, count, weighted_sum, first, first_time, last, last_time)
+                    VALUES (?1, ?2, ?3, ?3, ?3, 1, 0, ?3, ?4, ?3, ?4)
+                    ON CONFLICT (source, bucket) DO UPDATE SET
+                        min = MIN(min, excluded.min),
+                        max = MAX(max, excluded.max),
+                        sum = sum + excluded.sum,
+                        count = count + 1,
+                        weighted_sum = CASE
+                            WHEN excluded.last_time > last_time THEN weighted_sum + last * (excluded.last_time - last_time)
+                            WHEN excluded.first_time < first_time THEN weighted_sum + excluded.first * (first_time - excluded.first_time)
+                        END,
+                        first = CASE WHEN excluded.first_time < first_time THEN excluded.first ELSE first END,
+                        first_time = MIN(first_time, excluded.first_time),
+                        last = CASE WHEN excluded.last_time >= last_time THEN excluded.last ELSE last END,
+                        last_time = MAX(last_time, excluded.last_time)",
+                table,
+            ),
+            params![source, timestamp - timestamp.rem_euclid(size), temp, timestamp],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if the day of the given timestamp is archived, i.e. its daily rollup holds observations
+/// that are no longer in the raw data
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+/// * 'source' - sensor id (source)
+/// * 'timestamp' - unix timestamp within the day
+pub fn is_archived(conn: &Connection, source: &str, timestamp: i64) -> rusqlite::Result<bool> {
+    let day = timestamp - timestamp.rem_euclid(86400);
+
+    conn.query_row(
+        "SELECT EXISTS (
+            SELECT 1 FROM observation_daily
+            WHERE source = ?1 AND bucket = ?2 AND count > (
+                SELECT COUNT(*) FROM observation
+                WHERE source = ?1 AND datetime >= ?2 AND datetime < ?3 AND temperature IS NOT NULL
+            )
+        );",
+        params![source, day, day + 86400],
+        |row| row.get(0),
+    )
+}
+
+/// Rebuilds the hourly and daily rollups from the raw observations between the given timestamps.
+/// Buckets partly outside the range are rebuilt from the raw observations within the range only,
+/// so the range should be aligned to whole days.
+///
+/// Buckets holding more observations than the raw data, i.e. archived ones, are left as they are,
+/// since observations imported into them afterwards are already merged by `add_to_rollups`.
+///
+/// # Arguments
+///
+/// * 'conn' - database connection
+/// * 'source' - sensor id (source) to rebuild rollups for, all sources if None
+/// * 'from' - unix timestamp of the start of the range
+/// * 'to' - unix timestamp of the end of the range (non-inclusive)
+pub fn rebuild_rollups(conn: &Connection, source: Option<&str>, from: i64, to: i64) -> rusqlite::Result<()> {
+    for (table, size) in [Resolution::Hourly, Resolution::Daily].iter().filter_map(|r| r.table()) {
+        conn.execute(
+            &format!(
+                "INSERT INTO {table} (source, bucket, min, max, sum, count, weighted_sum, first, first_time, last, last_time)
+                    SELECT source, bucket, MIN(temperature), MAX(temperature), SUM(temperature), COUNT(*),
+                        COALESCE(SUM(weighted), 0), MIN(first), MIN(datetime), MIN(last), MAX(datetime)
+                    FROM (
+                        SELECT source, datetime - datetime % {size} AS bucket, datetime, temperature,
+                            FIRST_VALUE(temperature) OVER w AS first,
+                            LAST_VALUE(temperature) OVER w AS last,
+                            temperature * (LEAD(datetime) OVER w - datetime) AS weighted
+                        FROM observation
+                        WHERE (?1 IS NULL OR source = ?1) AND datetime >= ?2 AND datetime < ?3 AND temperature IS NOT NULL
+                        WINDOW w AS (
+                            PARTITION BY source, datetime - datetime % {size} ORDER BY datetime
+                            ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING
+                        )
+                    )
+                    GROUP BY source, bucket
+                    ON CONFLICT (source, bucket) DO UPDATE SET
+                        min = excluded.min, max = excluded.max, sum = excluded.sum, count = excluded.count,
+                        weighted_sum = excluded.weighted_sum, first = excluded.first, first_time = excluded.first_time,
+                        last = excluded.last, last_time = excluded.last_time
+                    WHERE excluded.count >= {table}.count;"
+            ),
+            params![source, from, to],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager_db::migrations;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn insert(conn: &Connection, timestamp: i64, temp: f64) {
+        conn.execute(
+            "INSERT INTO observation (source, datetime, temperature) VALUES ('east_west', ?1, ?2)", params![timestamp, temp],
+        ).unwrap();
+        add_to_rollups(conn, "east_west", timestamp, temp).unwrap();
+    }
+
+    fn hourly(conn: &Connection, bucket: i64) -> BucketAggregate {
+        conn.query_row(
+            &format!("SELECT {} FROM observation_hourly WHERE source = 'east_west' AND bucket = ?1", BUCKET_AGGREGATE_COLUMNS),
+            params![bucket],
+            BucketAggregate::from_row,
+        ).unwrap()
+    }
+
+    #[test]
+    fn mean_is_weighted_by_time_held() {
+        let conn = database();
+        // 10 degrees for 50 minutes, then 20 degrees for the last 10 minutes of the hour
+        insert(&conn, 0, 10.0);
+        insert(&conn, 3000, 20.0);
+
+        let bucket = hourly(&conn, 0);
+
+        assert_eq!(bucket.weighted_sum, Some(30000.0));
+        assert_eq!(bucket.sum / bucket.count as f64, 15.0);
+        assert!((bucket.mean(3600, None, i64::MAX) - 11.666).abs() < 0.001);
+    }
+
+    #[test]
+    fn mean_starts_with_temperature_held_from_before_the_bucket() {
+        let conn = database();
+        insert(&conn, 0, 10.0);
+        insert(&conn, 3600 + 1800, 20.0);
+
+        let means = time_weighted_means(&[hourly(&conn, 0), hourly(&conn, 3600)], 3600, None, i64::MAX);
+
+        assert_eq!(means.iter().map(|m| m.y).collect::<Vec<f64>>(), vec![10.0, 15.0]);
+    }
+
+    #[test]
+    fn mean_of_current_bucket_ends_now() {
+        let conn = database();
+        insert(&conn, 0, 10.0);
+        insert(&conn, 600, 20.0);
+
+        assert_eq!(hourly(&conn, 0).mean(3600, None, 1200), 15.0);
+    }
+
+    #[test]
+    fn weighted_sum_is_unknown_after_insert_between_observations_until_rebuilt() {
+        let conn = database();
+        insert(&conn, 0, 10.0);
+        insert(&conn, 3000, 20.0);
+        insert(&conn, 1000, 30.0);
+
+        assert_eq!(hourly(&conn, 0).weighted_sum, None);
+
+        rebuild_rollups(&conn, Some("east_west"), 0, 86400).unwrap();
+
+        assert_eq!(hourly(&conn, 0).weighted_sum, Some(10.0 * 1000.0 + 30.0 * 2000.0));
+    }
+
+    #[test]
+    fn rebuild_leaves_archived_buckets() {
+        let conn = database();
+        insert(&conn, 0, 10.0);
+        insert(&conn, 3000, 20.0);
+        conn.execute("DELETE FROM observation WHERE datetime < 3600", []).unwrap();
+        insert(&conn, 1000, 30.0);
+
+        assert!(is_archived(&conn, "east_west", 0).unwrap());
+
+        rebuild_rollups(&conn, None, 0, 86400).unwrap();
+
+        let bucket = hourly(&conn, 0);
+        assert_eq!((bucket.sum, bucket.count), (60.0, 3));
+    }
+}
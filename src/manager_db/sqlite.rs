@@ -0,0 +1,321 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::error;
+use rusqlite::Connection;
+use crate::manager_db::backup::backup_to_file;
+use crate::manager_db::errors::DBError;
+use crate::manager_db::export::{export, Export};
+use crate::manager_db::migrations;
+use crate::manager_db::models::{ForecastRecord, MinMax, Observation, SourceStats, Temperature};
+use crate::manager_db::pool::Pool;
+use crate::manager_db::queries::Queries;
+use crate::manager_db::retention::{apply_retention, RetentionPolicy};
+use crate::manager_db::rollups::{BucketAggregate, Resolution};
+use crate::manager_db::storage::Storage;
+
+/// SQLite storage, the default backend.
+///
+/// All statements run on tokio's blocking thread pool so that SQLite never blocks the async runtime,
+/// reads on a pool of read-only connections and writes on a single writer connection.
+pub struct SqliteStorage {
+    pool: Arc<Pool>,
+    retention: RetentionPolicy,
+}
+
+impl SqliteStorage {
+
+    /// Creates a new instance of SqliteStorage, migrating the database schema to the latest version if needed
+    ///
+    /// # Arguments
+    ///
+    /// * 'db_path' - full path to db file
+    /// * 'retention' - how long data is kept
+    /// * 'pool_size' - number of read-only connections
+    pub fn new(db_path: &str, retention: RetentionPolicy, pool_size: usize) -> Result<Self, DBError> {
+        let pool = Pool::new(db_path, pool_size, |conn| migrations::migrate(conn).map(|_| ()))?;
+
+        Ok(SqliteStorage { pool: Arc::new(pool), retention })
+    }
+
+    /// Migrates the database schema to the latest version without starting the application,
+    /// returns the schema versions before and after migration
+    ///
+    /// # Arguments
+    ///
+    /// * 'db_path' - full path to db file
+    pub fn migrate(db_path: &str) -> Result<(i64, i64), DBError> {
+        let mut db_conn = Connection::open(db_path)?;
+
+        migrations::migrate(&mut db_conn)
+    }
+
+    /// Runs the given read-only queries on a pooled connection on the blocking thread pool
+    ///
+    /// # Arguments
+    ///
+    /// * 'f' - function running the queries
+    async fn read<T: Send + 'static>(&self, f: impl FnOnce(Queries) -> Result<T, DBError> + Send + 'static) -> Result<T, DBError> {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || pool.read(|db_conn| f(Queries { db_conn })))
+            .await
+            .map_err(|e| DBError::Other(e.to_string()))?
+    }
+
+    /// Runs the given statements on the writer connection on the blocking thread pool
+    ///
+    /// # Arguments
+    ///
+    /// * 'f' - function running the statements
+    async fn write<T: Send + 'static>(&self, f: impl FnOnce(Queries) -> Result<T, DBError> + Send + 'static) -> Result<T, DBError> {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || pool.write(|db_conn| f(Queries { db_conn })))
+            .await
+            .map_err(|e| DBError::Other(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+
+    async fn insert_observation_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        humidity: Option<u8>,
+        perceived_temp: Option<f64>,
+    ) -> Result<(), DBError> {
+        let source = source.to_string();
+
+        self.write(move |q| q.insert_observation_record(&source, date_time, temp, humidity, perceived_temp)).await
+    }
+
+    async fn insert_forecast_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        wind_speed: Option<f64>,
+        humidity: Option<u8>,
+        lcc_mean: Option<u8>,
+        mcc_mean: Option<u8>,
+        hcc_mean: Option<u8>,
+        symbol_code: Option<u8>,
+        precipitation: Option<f64>,
+    ) -> Result<(), DBError> {
+        let source = source.to_string();
+
+        self.write(move |q| q.insert_forecast_record(
+            &source, date_time, temp, wind_speed, humidity, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation,
+        )).await
+    }
+
+    async fn temperature_history(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Temperature, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.temperature_history(&source, from, to, resolution)).await
+    }
+
+    async fn rollup_buckets(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Vec<BucketAggregate>, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.rollup_buckets(&source, from, to, resolution)).await
+    }
+
+    async fn forecast_records(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.get_forecast_records(&source, from, to)).await
+    }
+
+    async fn min_max(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Option<MinMax>, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.min_max(&source, from, to)).await
+    }
+
+    async fn latest_observations(&self, source: Option<&str>) -> Result<Vec<Observation>, DBError> {
+        let source = source.map(str::to_string);
+
+        self.read(move |q| q.latest_observations(source.as_deref())).await
+    }
+
+    async fn source_stats(&self) -> Result<Vec<SourceStats>, DBError> {
+        self.read(|q| q.source_stats()).await
+    }
+
+    async fn has_source(&self, source: &str) -> Result<bool, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.has_source(&source)).await
+    }
+
+    async fn apply_retention(&self) {
+        let retention = self.retention;
+
+        if let Err(e) = self.write(move |q| apply_retention(q.db_conn, &retention)).await {
+            error!("error while applying retention policy: {}", e);
+        }
+    }
+
+    async fn backup(&self, dest: PathBuf) -> Result<(), DBError> {
+        self.read(move |q| backup_to_file(q.db_conn, &dest)).await
+    }
+
+    fn supports_export(&self) -> bool {
+        true
+    }
+
+    async fn export(&self, export_request: Export, mut out: Box<dyn Write + Send>) -> Result<u64, DBError> {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || export(|f| pool.read(|db_conn| f(db_conn)), &export_request, &mut out))
+            .await
+            .map_err(|e| DBError::Other(e.to_string()))?
+    }
+
+    async fn interpolated_forecast(&self, source: &str, date_time: DateTime<Utc>) -> Result<Option<ForecastRecord>, DBError> {
+        let source = source.to_string();
+
+        self.read(move |q| q.interpolated_forecast(&source, date_time)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use rusqlite::params;
+    use crate::local_time::{day_range, local_date, CalendarPeriod};
+    use crate::manager_db::models::Forecast;
+    use crate::resample::Resampling;
+    use crate::statistics::{bucket_stats, history_spans, Stat};
+
+    /// Returns a storage on a new database file in the temporary directory, removed again by `remove`
+    fn storage(name: &str, retention: RetentionPolicy) -> (SqliteStorage, String) {
+        let path = std::env::temp_dir().join(format!("weatherlogger-sqlite-{}-{}.db", name, std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        remove(&path);
+
+        (SqliteStorage::new(&path, retention, 2).unwrap(), path)
+    }
+
+    fn remove(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn local_days_keep_min_max_after_retention() {
+        let retention = RetentionPolicy { observation_days: 30, forecast_days: 30, hourly_days: Some(30), daily_days: None };
+        let (storage, path) = storage("local_days", retention);
+        let tz = chrono_tz::Europe::Stockholm;
+        let now = Utc::now();
+        let date = local_date(now - TimeDelta::days(100), tz);
+        let (start, end) = day_range(date, tz);
+
+        // The UTC days overlapping the local day also hold colder and warmer temperatures of the days around it
+        let observations = [
+            (start - TimeDelta::minutes(30), -10.0),
+            (start + TimeDelta::minutes(30), 3.0),
+            (start + TimeDelta::hours(12), 8.0),
+            (end + TimeDelta::minutes(30), 20.0),
+        ];
+        storage.write(move |q| {
+            for (date_time, temp) in observations {
+                q.db_conn.execute(
+                    "INSERT INTO observation (source, datetime, temperature) VALUES ('east_west', ?1, ?2)",
+                    params![date_time.timestamp(), temp],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        storage.apply_retention().await;
+
+        let raw = storage.temperature_history("east_west", start, end, Resolution::Raw).await.unwrap();
+        assert!(raw.history.is_empty());
+
+        let series = storage.get_min_max_series("east_west", start, end, CalendarPeriod::Day, now, tz).await.unwrap();
+        remove(&path);
+
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].date, date);
+        assert_eq!((series[0].min_max.min, series[0].min_max.max), (3.0, 8.0));
+    }
+
+    #[tokio::test]
+    async fn first_summary_counts_precipitation_since_previous_record() {
+        let retention = RetentionPolicy { observation_days: 30, forecast_days: 30, hourly_days: None, daily_days: None };
+        let (storage, path) = storage("summary_precipitation", retention);
+        let time = |hour: i64| DateTime::from_timestamp(1_750_000_000 / 86400 * 86400, 0).unwrap() + TimeDelta::hours(hour);
+
+        // Beyond the first hours the forecast steps grow from 3 to 6 hours, each record holding the mean
+        // intensity in mm/h since the previous one
+        for (hour, precipitation) in [(3, 1.0), (6, 2.0), (12, 0.5)] {
+            storage.insert_forecast_record("smhi", time(hour), 10.0, None, None, None, None, None, None, Some(precipitation)).await.unwrap();
+        }
+
+        let result = storage.get_forecast("smhi", time(6), time(18), None, Some(Resampling::Aggregate(TimeDelta::hours(6)))).await;
+        remove(&path);
+
+        let Forecast::Summaries(summaries) = result.unwrap() else { panic!("summaries expected") };
+        let totals = summaries.iter().map(|s| (s.date_time, s.total_precipitation)).collect::<Vec<_>>();
+        assert_eq!(totals, vec![(time(6), Some(6.0)), (time(12), Some(3.0))]);
+    }
+
+    #[tokio::test]
+    async fn long_range_stats_match_raw_observations() {
+        let retention = RetentionPolicy { observation_days: 30, forecast_days: 30, hourly_days: None, daily_days: None };
+        let (storage, path) = storage("long_stats", retention);
+        let tz = chrono_tz::Europe::Stockholm;
+        let now = Utc::now();
+        let first = now - TimeDelta::days(12);
+
+        // A temperature every 20 minutes, with a few hours without any change
+        storage.write(move |q| {
+            for i in 0..(11 * 72) {
+                if (100..112).contains(&(i % 200)) {
+                    continue;
+                }
+                q.insert_observation_record("east_west", first + TimeDelta::minutes(20 * i), ((i * 7) % 23) as f64 - 5.0, None, None)?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        let stats = vec![Stat::Min, Stat::Max, Stat::Mean];
+        let (from, to) = (first + TimeDelta::minutes(50), now - TimeDelta::hours(20) - TimeDelta::minutes(10));
+        let raw = storage.temperature_history("east_west", from, to, Resolution::Raw).await.unwrap();
+
+        for bucket in [None, Some(TimeDelta::days(1)), Some(TimeDelta::hours(6))] {
+            let expected = bucket_stats(&history_spans(&raw.history, to), from, to, bucket, tz, &stats);
+            let result = storage.get_temp_stats("east_west", from, to, "smhi", bucket, tz, stats.clone(), now).await.unwrap();
+
+            assert_eq!(result.buckets.len(), expected.len());
+            for (r, e) in result.buckets.iter().zip(expected.iter()) {
+                assert_eq!((r.x, r.min, r.max), (e.x, e.min, e.max));
+                assert!((r.mean.unwrap() - e.mean.unwrap()).abs() < 1e-9, "{:?} != {:?}", r.mean, e.mean);
+            }
+            assert_eq!(result.current_temp, raw.current_temp);
+        }
+        remove(&path);
+    }
+}
@@ -0,0 +1,594 @@
+use std::io::Write;
+use std::path::PathBuf;
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use crate::interpolation::{interpolate_at, MAX_INTERPOLATION_DISTANCE};
+use chrono_tz::Tz;
+use crate::local_time::{local_date, start_of_day, CalendarPeriod};
+use crate::manager_db::errors::DBError;
+use crate::manager_db::export::Export;
+use crate::manager_db::models::{
+    BucketedTemperature, CurrentConditions, DailyForecast, Forecast, ForecastRecord, MinMax, MinMaxPeriod, Observation,
+    SourceStats, Temperature, Trend,
+};
+use crate::manager_db::rollups::{BucketAggregate, Resolution};
+use crate::perceived_temperature::perceived_temperature;
+use crate::resample::{aggregate, aggregate_daily, resample, Resampling};
+use crate::statistics::{bucket_stats, history_spans, rollup_spans, Stat};
+use crate::weather_symbols::symbol_info;
+
+/// Storage of observations and forecasts.
+///
+/// A backend implements inserts, retention and a few basic queries, while everything computed from
+/// the stored records (perceived temperature, interpolation, resampling, daily summaries and weather
+/// symbols) is provided here so that all backends return the same results.
+#[async_trait]
+pub trait Storage: Send + Sync {
+
+    /// Inserts an observation record and adds it to the hourly and daily rollups
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'date_time' - time of the observation
+    /// * 'temp' - temperature
+    /// * 'humidity' - humidity
+    /// * 'perceived_temp' - perceived temperature
+    async fn insert_observation_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        humidity: Option<u8>,
+        perceived_temp: Option<f64>,
+    ) -> Result<(), DBError>;
+
+    /// Inserts (or updates) a forecast record
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'date_time' - forecast date and time in UTC
+    /// * 'temp' - temperature
+    /// * 'wind_speed' - wind speed
+    /// * 'humidity' - humidity
+    /// * 'lcc_mean' - low level cloud index
+    /// * 'mcc_mean' - medium level cloud index
+    /// * 'hcc_mean' - high level cloud index
+    /// * 'symbol_code' - weather symbol code
+    /// * 'precipitation' - mean precipitation intensity in mm/h
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_forecast_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        wind_speed: Option<f64>,
+        humidity: Option<u8>,
+        lcc_mean: Option<u8>,
+        mcc_mean: Option<u8>,
+        hcc_mean: Option<u8>,
+        symbol_code: Option<u8>,
+        precipitation: Option<f64>,
+    ) -> Result<(), DBError>;
+
+    /// Returns the temperature history between (non-inclusive) given boundaries together with the current
+    /// temperature, i.e. the last one recorded before `to`. Perceived temperature is left for the caller.
+    ///
+    /// In raw resolution the last known temperature before the range is included with the time `from`,
+    /// since the sensor only records changes. In hourly or daily resolution the mean temperature of each
+    /// hour or day is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'resolution' - resolution of the history
+    async fn temperature_history(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Temperature, DBError>;
+
+    /// Returns the hourly or daily rollup buckets between (non-inclusive) given boundaries ordered by time,
+    /// including those whose raw observations have been archived by the retention policy
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range, the bucket containing it is included
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'resolution' - hourly or daily rollups, none for raw resolution
+    async fn rollup_buckets(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Vec<BucketAggregate>, DBError>;
+
+    /// Returns whatever forecast records are recorded between given boundaries, ordered by time
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    async fn forecast_records(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, DBError>;
+
+    /// Returns the min/max temperature between (non-inclusive) given boundaries and when they first occurred,
+    /// including the last known temperature before the range, occurring at `from`. For days whose raw observations
+    /// are gone only the hourly and daily rollups lying fully within the range are used, where the time of the
+    /// extremes isn't known, so extremes of archived hours or days partly outside the range are left out
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    async fn min_max(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Option<MinMax>, DBError>;
+
+    /// Returns the latest observation of each source ordered by source, or of the given source only
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source), all sources if None
+    async fn latest_observations(&self, source: Option<&str>) -> Result<Vec<Observation>, DBError>;
+
+    /// Returns what is stored for each source, ordered by source and kind
+    ///
+    async fn source_stats(&self) -> Result<Vec<SourceStats>, DBError>;
+
+    /// Returns true if anything is stored for the given source, as an observation, forecast or rollup
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source to look for
+    async fn has_source(&self, source: &str) -> Result<bool, DBError>;
+
+    /// Applies the retention policy, archiving old observations into the rollups before deleting them
+    ///
+    async fn apply_retention(&self);
+
+    /// Makes an online backup of the database to the given file
+    ///
+    /// # Arguments
+    ///
+    /// * 'dest' - path of the backup file, which must not already exist
+    async fn backup(&self, _dest: PathBuf) -> Result<(), DBError> {
+        Err(DBError::Other("backup is not supported by this storage backend".to_string()))
+    }
+
+    /// Returns true if the backend supports `export`, which is checked before a streamed export is begun
+    ///
+    fn supports_export(&self) -> bool {
+        false
+    }
+
+    /// Streams the requested observations and/or forecasts to the given writer, returns the number of exported rows
+    ///
+    /// # Arguments
+    ///
+    /// * 'export_request' - what to export and how
+    /// * 'out' - writer receiving the export
+    async fn export(&self, _export_request: Export, _out: Box<dyn Write + Send>) -> Result<u64, DBError> {
+        Err(DBError::Other("export is not supported by this storage backend".to_string()))
+    }
+
+    /// Returns a forecast record interpolated between the stored records closest before and after
+    /// the given time. If there is a stored record at exactly the given time, it is returned as is.
+    ///
+    /// Records further away than `MAX_INTERPOLATION_DISTANCE` are not considered, so None is returned
+    /// for times outside what the stored forecast covers.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'date_time' - datetime to get a forecast for
+    async fn interpolated_forecast(&self, source: &str, date_time: DateTime<Utc>) -> Result<Option<ForecastRecord>, DBError> {
+        let records = self.forecast_records(
+            source,
+            date_time - MAX_INTERPOLATION_DISTANCE,
+            date_time + MAX_INTERPOLATION_DISTANCE + TimeDelta::seconds(1),
+        ).await?;
+
+        Ok(interpolate_at(&records, date_time))
+    }
+
+    /// Returns whatever temperatures are recorded between (non-inclusive) given boundaries
+    ///
+    /// Since the sensor only records data when there is a change in either temperature (1 degree Celsius) or
+    /// humidity (5%), there is a chance that no data would be returned even for a longer period of time.
+    ///
+    /// To mitigate this, indeed there is a temperature, the last recorded temperature will be returned with
+    /// a time set to the given `from` parameter if the resultset from db was empty.
+    ///
+    /// For longer ranges the history is taken from the hourly or daily rollups instead, with the mean
+    /// temperature of each hour or day, unless a resolution is explicitly requested.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'forecast_source' - forecast source to get wind and humidity from for perceived temperature
+    /// * 'resolution' - resolution of the history, chosen from the length of the range if not given
+    async fn get_temp_history(
+        &self,
+        source: &str,
+        from_datetime: DateTime<Utc>,
+        to_datetime: DateTime<Utc>,
+        forecast_source: &str,
+        resolution: Option<Resolution>,
+    ) -> Result<Temperature, DBError> {
+        let resolution = resolution.unwrap_or_else(|| Resolution::for_range(from_datetime, to_datetime));
+
+        let mut result = self.temperature_history(source, from_datetime, to_datetime, resolution).await?;
+
+        if let Some(temp) = result.current_temp {
+            if let Some((ws, h)) = self.get_wind_and_humidity(forecast_source, to_datetime).await? {
+                result.perceived_temp = Some(perceived_temperature(temp, h as f64, ws))
+            } else {
+                result.perceived_temp = Some(temp);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns statistics of the temperatures between (non-inclusive) given boundaries per bucket, or for
+    /// the whole range if no bucket size is given, see `bucket_stats`. The last temperature is taken to hold
+    /// until 'to' or now, whichever is first.
+    ///
+    /// Statistics of short ranges are calculated from the raw observations. Long ranges with buckets of whole hours
+    /// would need too many of them, so the hourly rollups are used for the whole hours within the range and raw
+    /// observations only for the hours partly within it at both ends, whereby median and percentiles are those of
+    /// the hourly means. Where the raw observations have been archived the hourly rollups are used instead, which are
+    /// kept as long as the daily ones (see `RetentionPolicy::rollup_days`) since those don't follow local days.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'forecast_source' - forecast source to get wind and humidity from for perceived temperature
+    /// * 'bucket' - bucket size
+    /// * 'tz' - time zone to align buckets of whole days in
+    /// * 'stats' - statistics to calculate
+    /// * 'now' - the current time
+    #[allow(clippy::too_many_arguments)]
+    async fn get_temp_stats(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        forecast_source: &str,
+        bucket: Option<TimeDelta>,
+        tz: Tz,
+        stats: Vec<Stat>,
+        now: DateTime<Utc>,
+    ) -> Result<BucketedTemperature, DBError> {
+        let end = to.min(now);
+        let hour = TimeDelta::hours(1);
+        let rollups = self.rollup_buckets(source, from, to, Resolution::Hourly).await?.into_iter()
+            .map(|b| (b, hour.num_seconds()))
+            .collect::<Vec<(BucketAggregate, i64)>>();
+
+        // The whole hours within a long range, if the buckets are of whole hours too
+        let hours = (Resolution::for_range(from, to) != Resolution::Raw && bucket.is_none_or(|b| b.num_seconds() % 3600 == 0))
+            .then(|| from.duration_round_up(hour).ok().zip(end.duration_trunc(hour).ok()))
+            .flatten()
+            .filter(|(start, stop)| start < stop);
+
+        let (spans, temperature) = match hours {
+            None => {
+                let temperature = self.get_temp_history(source, from, to, forecast_source, Some(Resolution::Raw)).await?;
+
+                // Rollups of archived observations before the raw history
+                let raw_start = temperature.history.first().map_or(end, |item| item.x);
+                let archived = rollups.iter()
+                    .filter(|(b, size)| DateTime::from_timestamp(b.bucket + size, 0).is_some_and(|end| end <= raw_start))
+                    .copied()
+                    .collect::<Vec<(BucketAggregate, i64)>>();
+
+                let mut spans = rollup_spans(&archived, None, raw_start, now);
+                spans.extend(history_spans(&temperature.history, end));
+                (spans, temperature)
+            },
+            Some((start, stop)) => {
+                let head = self.temperature_history(source, from, start, Resolution::Raw).await?.history;
+                let tail = self.get_temp_history(source, stop, to, forecast_source, Some(Resolution::Raw)).await?;
+                let (before, rest) = rollups.split_at(rollups.partition_point(|(b, _)| b.bucket < start.timestamp()));
+                let (within, after) = rest.split_at(rest.partition_point(|(b, _)| b.bucket < stop.timestamp()));
+
+                // The rollups partly within the range only stand in for raw observations that have been archived
+                let mut spans = if head.is_empty() { rollup_spans(before, None, start, now) } else { history_spans(&head, start) };
+                let held = head.last().map(|item| item.y).or(before.last().map(|(b, _)| b.last));
+                spans.extend(rollup_spans(within, held.map(|temp| (start, temp)), stop, now));
+
+                let held = within.last().map(|(b, _)| b.last).or(held);
+                if tail.history.is_empty() {
+                    spans.extend(rollup_spans(after, held.map(|temp| (stop, temp)), end, now));
+                } else {
+                    spans.extend(history_spans(&tail.history, end));
+                }
+                (spans, tail)
+            },
+        };
+
+        Ok(BucketedTemperature {
+            buckets: bucket_stats(&spans, from, end, bucket, tz, &stats),
+            stats,
+            bucket_secs: bucket.map(|b| b.num_seconds()),
+            current_temp: temperature.current_temp,
+            perceived_temp: temperature.perceived_temp,
+        })
+    }
+
+    /// Returns whatever forecasts are recorded between (non-inclusive) given boundaries
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'location' - lat/long of the forecast location, if given each record is amended with symbol information
+    /// * 'resampling' - if given, the forecast is resampled onto a regular time grid
+    async fn get_forecast(
+        &self,
+        source: &str,
+        from_datetime: DateTime<Utc>,
+        to_datetime: DateTime<Utc>,
+        location: Option<(f64, f64)>,
+        resampling: Option<Resampling>,
+    ) -> Result<Forecast, DBError> {
+        match resampling {
+            None => {
+                let mut result = self.forecast_records(source, from_datetime, to_datetime).await?;
+                result.iter_mut().for_each(|fc| add_symbol_info(fc, location));
+
+                Ok(Forecast::Records(result))
+            },
+            Some(Resampling::Interpolate(step)) => {
+                // Records outside the boundaries are needed to interpolate grid points close to the boundaries
+                let records = self.forecast_records(
+                    source,
+                    from_datetime - MAX_INTERPOLATION_DISTANCE,
+                    to_datetime + MAX_INTERPOLATION_DISTANCE,
+                ).await?;
+
+                let mut result = resample(&records, from_datetime, to_datetime, step);
+                result.iter_mut().for_each(|fc| add_symbol_info(fc, location));
+
+                Ok(Forecast::Records(result))
+            },
+            Some(Resampling::Aggregate(step)) => {
+                // The record before the first step is needed to know the period covered by the first record's
+                // precipitation, records before the first step are otherwise left out by the aggregation
+                let records = self.forecast_records(
+                    source,
+                    from_datetime - MAX_INTERPOLATION_DISTANCE,
+                    to_datetime,
+                ).await?;
+
+                let mut result = aggregate(&records, from_datetime, to_datetime, step);
+                for s in result.iter_mut() {
+                    // The symbol is presented as valid for the middle of the step
+                    s.symbol = s.symbol_code
+                        .zip(location)
+                        .and_then(|(code, (lat, long))| symbol_info(code, s.date_time + step / 2, lat, long));
+                }
+
+                Ok(Forecast::Summaries(result))
+            },
+        }
+    }
+
+    /// Returns a forecast summary per local calendar day, i.e. min/max temperature,
+    /// total precipitation, max wind speed and dominant weather symbol
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'from' - datetime within the first day, defaults to today
+    /// * 'to' - end of the last day (non-inclusive), defaults to 'days' days after 'from'
+    /// * 'days' - number of days if 'to' isn't given
+    /// * 'location' - lat/long of the forecast location, if given each day is amended with symbol information
+    /// * 'tz' - time zone of the calendar days
+    async fn get_daily_forecast(
+        &self,
+        source: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        days: i64,
+        location: Option<(f64, f64)>,
+        tz: Tz,
+    ) -> Result<Vec<DailyForecast>, DBError> {
+
+        let from_date = match from {
+            Some(from) => local_date(from, tz),
+            None => local_date(Utc::now(), tz),
+        };
+        let to_date = match to {
+            Some(to) => local_date(to - TimeDelta::seconds(1), tz),
+            None => TimeDelta::try_days(days - 1)
+                .and_then(|d| from_date.checked_add_signed(d))
+                .ok_or(DBError::Other(format!("daily forecast of {} days is out of range", days)))?,
+        };
+
+        // The record before the first day is needed to know the period covered by the first record's precipitation
+        let records = self.forecast_records(
+            source,
+            start_of_day(from_date, tz) - MAX_INTERPOLATION_DISTANCE,
+            start_of_day(to_date.succ_opt().unwrap_or(to_date), tz),
+        ).await?;
+
+        let mut result = aggregate_daily(&records, from_date, to_date, tz);
+        for d in result.iter_mut() {
+            // The symbol is presented as valid for local noon
+            let noon = start_of_day(d.date, tz) + TimeDelta::hours(12);
+            d.symbol = d.symbol_code
+                .zip(location)
+                .and_then(|(code, (lat, long))| symbol_info(code, noon, lat, long));
+        }
+
+        Ok(result)
+    }
+
+    /// Returns a forecast interpolated for the given time, or None if the stored forecast doesn't cover that time
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'date_time' - datetime to get a forecast for
+    /// * 'location' - lat/long of the forecast location, if given the record is amended with symbol information
+    async fn get_forecast_at(&self, source: &str, date_time: DateTime<Utc>, location: Option<(f64, f64)>) -> Result<Option<ForecastRecord>, DBError> {
+        let mut result = self.interpolated_forecast(source, date_time).await?;
+        if let Some(fc) = result.as_mut() {
+            add_symbol_info(fc, location);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns min/max temperature values, or None if no temperatures are recorded for the range
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    async fn get_min_max(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Option<MinMax>, DBError> {
+        self.min_max(source, from, to).await
+    }
+
+    /// Returns the current conditions of each observation source, or of the given source only.
+    ///
+    /// Besides the latest observation and its age, each snapshot holds the forecast of the current hour, the
+    /// min/max temperature of the local day so far and whether the temperature rose or fell over the last hour.
+    /// The perceived temperature is computed from the forecast wind and humidity, falling back on the one
+    /// stored with the observation.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source), all sources if None
+    /// * 'forecast_source' - forecast source to get current forecast values from
+    /// * 'location' - lat/long of the forecast location, if given the forecast is amended with symbol information
+    /// * 'now' - the current time
+    /// * 'tz' - time zone of the calendar day
+    async fn get_current(
+        &self,
+        source: Option<&str>,
+        forecast_source: &str,
+        location: Option<(f64, f64)>,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<CurrentConditions>, DBError> {
+        let hour = now.duration_trunc(TimeDelta::hours(1)).unwrap_or(now);
+        let mut forecast = self.interpolated_forecast(forecast_source, hour).await?;
+        if let Some(fc) = forecast.as_mut() {
+            add_symbol_info(fc, location);
+        }
+
+        let mut result = Vec::new();
+        for observation in self.latest_observations(source).await? {
+            let last_hour = self.temperature_history(&observation.source, now - TimeDelta::hours(1), now, Resolution::Raw).await?;
+            let trend = last_hour.history.first()
+                .zip(observation.temperature)
+                .map(|(hour_ago, temp)| match temp - hour_ago.y {
+                    d if d > 0.0 => Trend::Rising,
+                    d if d < 0.0 => Trend::Falling,
+                    _ => Trend::Steady,
+                });
+
+            let today = self.min_max(&observation.source, start_of_day(local_date(now, tz), tz), now + TimeDelta::seconds(1)).await?;
+
+            let perceived_temperature = observation.temperature
+                .zip(forecast.as_ref().and_then(|fc| fc.wind_speed.zip(fc.humidity)))
+                .map(|(temp, (ws, h))| perceived_temperature(temp, h as f64, ws))
+                .or(observation.perceived_temperature);
+
+            result.push(CurrentConditions {
+                age_secs: (now - observation.date_time).num_seconds().max(0),
+                source: observation.source,
+                date_time: observation.date_time,
+                temperature: observation.temperature,
+                humidity: observation.humidity,
+                perceived_temperature,
+                trend,
+                today,
+                forecast: forecast.clone(),
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Returns min/max temperature values per local calendar day, week (starting on Monday) or month between
+    /// (non-inclusive) given boundaries. The first and last period only cover the part within the boundaries,
+    /// and periods without temperatures, including those not yet begun, are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - sensor id (source)
+    /// * 'from' - start of the range
+    /// * 'to' - end of the range (non-inclusive)
+    /// * 'per' - calendar period
+    /// * 'now' - the current time
+    /// * 'tz' - time zone of the calendar periods
+    async fn get_min_max_series(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        per: CalendarPeriod,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<MinMaxPeriod>, DBError> {
+        let last = local_date(to - TimeDelta::seconds(1), tz);
+
+        let mut result = Vec::new();
+        let mut date = per.start(local_date(from, tz));
+        while date <= last {
+            let next = per.next(date);
+            let start = start_of_day(date, tz).max(from);
+            let end = start_of_day(next, tz).min(to).min(now + TimeDelta::seconds(1));
+
+            if start < end {
+                if let Some(min_max) = self.min_max(source, start, end).await? {
+                    result.push(MinMaxPeriod { date, min_max });
+                }
+            }
+            date = next;
+        }
+
+        Ok(result)
+    }
+
+    /// Returns wind speed and humidity interpolated from the forecast for the given datetime
+    ///
+    /// # Arguments
+    ///
+    /// * 'source' - source responsible for forecast values
+    /// * 'date_time' - datetime to get data for
+    async fn get_wind_and_humidity(&self, source: &str, date_time: DateTime<Utc>) -> Result<Option<(f64, u8)>, DBError> {
+        let forecast = self.interpolated_forecast(source, date_time).await?;
+
+        Ok(forecast.and_then(|fc| fc.wind_speed.zip(fc.humidity)))
+    }
+}
+
+/// Amends a forecast record with symbol information if a location is given
+///
+/// # Arguments
+///
+/// * 'fc' - the forecast record
+/// * 'location' - lat/long of the forecast location
+fn add_symbol_info(fc: &mut ForecastRecord, location: Option<(f64, f64)>) {
+    fc.symbol = fc.symbol_code
+        .zip(location)
+        .and_then(|(code, (lat, long))| symbol_info(code, fc.date_time, lat, long));
+}
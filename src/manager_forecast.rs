@@ -1,7 +1,8 @@
-use std::sync::Arc;
 use chrono::Utc;
+use chrono_tz::Tz;
 use log::error;
-use tokio::sync::Mutex;
+use crate::events::{publish, Event, Events, ForecastRefresh};
+use crate::initialization::{ForecastProvider, WeatherForecast};
 use crate::manager_db::DB;
 use crate::manager_smhi::SMHI;
 
@@ -10,21 +11,33 @@ use crate::manager_smhi::SMHI;
 /// # Arguments
 ///
 /// * 'db' - database to store readings into
-/// * 'lat' - latitude of the location
-/// * 'long' - longitude of the location
-/// * 'name' - the name of the forecast provider
-pub async fn run_forecasts(db: Arc<Mutex<DB>>, lat: f64, long: f64, name: &str) {
-    let smhi = if let Ok(smhi) = SMHI::new(lat, long) {
+/// * 'events' - sender of events about refreshed forecasts
+/// * 'forecast' - configuration of the forecast location
+/// * 'tz' - time zone of the calendar days to get forecasts for
+pub async fn run_forecasts(db: DB, events: Events, forecast: WeatherForecast, tz: Tz) {
+    let name = forecast.name.as_str();
+    let smhi = match forecast.provider {
+        ForecastProvider::Smhi => SMHI::new(forecast.lat, forecast.long),
+    };
+    let smhi = if let Ok(smhi) = smhi {
         smhi
     } else {
-        error!("failed to create SMHI manager");
+        error!("failed to create SMHI manager for {}", name);
         return;
     };
 
     loop {
-        if let Ok(forecast) = smhi.new_forecast(Utc::now()).await {
+        if let Ok(forecast) = smhi.new_forecast(Utc::now(), forecast.days, tz).await {
+            let refresh = ForecastRefresh {
+                source: name.to_string(),
+                date_time: Utc::now(),
+                first: forecast.iter().map(|f| f.valid_time).min(),
+                last: forecast.iter().map(|f| f.valid_time).max(),
+                count: forecast.len(),
+            };
+
             for f in forecast {
-                if let Err(e) = db.lock().await.insert_forecast_record(
+                if let Err(e) = db.insert_forecast_record(
                     name,
                     f.valid_time,
                     f.temp,
@@ -34,14 +47,17 @@ pub async fn run_forecasts(db: Arc<Mutex<DB>>, lat: f64, long: f64, name: &str)
                     Some(f.mcc_mean),
                     Some(f.hcc_mean),
                     Some(f.symbol_code),
-                ) {
+                    f.precipitation,
+                ).await {
                     error!("failed to insert forecast record: {}", e);
                 }
             }
+
+            publish(&events, Event::Forecast(refresh));
         } else {
-            error!("failed to get forecast from SMHI");
+            error!("failed to get forecast from SMHI for {}", name);
         }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
+        tokio::time::sleep(tokio::time::Duration::from_secs(forecast.refresh_interval_secs)).await;
     }
 }
\ No newline at end of file
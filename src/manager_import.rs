@@ -0,0 +1,464 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::info;
+use serde_json::Value;
+use crate::errors::ImportError;
+use crate::initialization::option;
+use crate::manager_db::import::{ImportResult, ImportRow, Importer};
+
+/// Lowest temperature accepted on import
+const MIN_TEMPERATURE: f64 = -90.0;
+
+/// Highest temperature accepted on import
+const MAX_TEMPERATURE: f64 = 60.0;
+
+/// Number of skipped rows reported individually on the command line, all are logged
+const MAX_REPORTED_SKIPS: usize = 20;
+
+/// Format of the file to import
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    /// One json object per line
+    Ndjson,
+}
+
+/// How timestamps in the file to import are formatted
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimeFormat {
+    Rfc3339,
+    /// Seconds since the epoch
+    Unix,
+    /// A chrono format string, e.g. "%Y-%m-%d %H:%M:%S". Times without offset are in the `--tz` time zone, UTC by default.
+    Pattern(String),
+}
+
+/// Options for an import, given on the command line
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    pub source: String,
+    pub input: String,
+    pub format: InputFormat,
+    pub delimiter: u8,
+    pub skip_lines: usize,
+    pub time_columns: Vec<String>,
+    pub time_format: TimeFormat,
+    pub time_zone: Tz,
+    pub temperature_column: String,
+    pub humidity_column: Option<String>,
+    pub perceived_column: Option<String>,
+    pub replace: bool,
+    pub batch_size: usize,
+}
+
+impl ImportOptions {
+    /// Returns the import options given on the command line as --<name>=<value>
+    ///
+    pub fn from_command_line() -> Result<Self, ImportError> {
+        let source = option("id").ok_or(ImportError::from("missing --id=<source>"))?;
+        let input = option("input").ok_or(ImportError::from("missing --input=<path>"))?;
+
+        let format = match option("format").as_deref() {
+            Some("csv") => InputFormat::Csv,
+            Some("ndjson") | Some("jsonl") => InputFormat::Ndjson,
+            Some(other) => return Err(ImportError(format!("unknown import format: {}", other))),
+            None if input.ends_with(".ndjson") || input.ends_with(".jsonl") => InputFormat::Ndjson,
+            None => InputFormat::Csv,
+        };
+
+        let delimiter = match option("delimiter").as_deref() {
+            None => b',',
+            Some("tab") | Some("\\t") => b'\t',
+            Some(d) if d.len() == 1 => d.as_bytes()[0],
+            Some(d) => return Err(ImportError(format!("delimiter must be a single character: {}", d))),
+        };
+
+        let time_format = match option("time-format").as_deref() {
+            None | Some("rfc3339") => TimeFormat::Rfc3339,
+            Some("unix") => TimeFormat::Unix,
+            Some(pattern) => TimeFormat::Pattern(pattern.to_string()),
+        };
+
+        let replace = match option("on-conflict").as_deref() {
+            None | Some("skip") => false,
+            Some("replace") => true,
+            Some(other) => return Err(ImportError(format!("unknown conflict handling: {}", other))),
+        };
+
+        let number = |name: &str, default: usize| option(name)
+            .map(|n| n.parse::<usize>().map_err(|e| ImportError(format!("invalid --{}: {}", name, e))))
+            .unwrap_or(Ok(default));
+
+        Ok(ImportOptions {
+            source,
+            input,
+            format,
+            delimiter,
+            skip_lines: number("skip-lines", 0)?,
+            time_columns: option("time-column").unwrap_or("datetime".to_string()).split('+').map(str::to_string).collect(),
+            time_format,
+            time_zone: option("tz").unwrap_or("UTC".to_string()).parse::<Tz>()
+                .map_err(|e| ImportError(format!("invalid --tz: {}", e)))?,
+            temperature_column: option("temperature-column").unwrap_or("temperature".to_string()),
+            humidity_column: option("humidity-column"),
+            perceived_column: option("perceived-column"),
+            replace,
+            batch_size: number("batch-size", 1000)?.max(1),
+        })
+    }
+}
+
+/// Imports observations from a file into the database, rows are validated and inserted in batches
+/// of one transaction each. Rows that fail validation are skipped and reported together with their
+/// line number.
+///
+/// # Arguments
+///
+/// * 'db_path' - full path to db file
+/// * 'options' - what to import and how
+pub fn import(db_path: &str, options: &ImportOptions) -> Result<ImportResult, ImportError> {
+    let mut reader = BufReader::new(File::open(&options.input)?);
+    let mut line = String::new();
+    for _ in 0..options.skip_lines {
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+
+    let mut importer = Importer::open(db_path, &options.source, options.replace)?;
+    let mut batch = Vec::with_capacity(options.batch_size);
+    let mut skipped = 0;
+
+    let mut handle = |line_number: u64, row: Result<ImportRow, String>, batch: &mut Vec<ImportRow>| -> Result<(), ImportError> {
+        match row {
+            Ok(row) => {
+                batch.push(row);
+                if batch.len() >= options.batch_size {
+                    importer.insert_batch(batch)?;
+                    batch.clear();
+                }
+            },
+            Err(reason) => {
+                skipped += 1;
+                info!("skipped line {}: {}", line_number, reason);
+                if skipped <= MAX_REPORTED_SKIPS {
+                    eprintln!("skipped line {}: {}", line_number, reason);
+                }
+            }
+        }
+
+        Ok(())
+    };
+
+    let first_line = options.skip_lines as u64 + 1;
+    match options.format {
+        InputFormat::Csv => {
+            let mut csv_reader = csv::ReaderBuilder::new()
+                .delimiter(options.delimiter)
+                .flexible(true)
+                .from_reader(reader);
+            let headers = csv_reader.headers()?.clone();
+
+            // A column missing from the header would otherwise skip or leave out a value of every row
+            let columns = options.time_columns.iter()
+                .chain([&options.temperature_column])
+                .chain(options.humidity_column.iter())
+                .chain(options.perceived_column.iter());
+            for column in columns {
+                if !headers.iter().any(|h| h.trim() == column) {
+                    return Err(ImportError(format!("column {} not found in header: {}", column, headers.iter().collect::<Vec<&str>>().join(","))));
+                }
+            }
+
+            for record in csv_reader.records() {
+                let (line_number, row) = match record {
+                    Ok(record) => {
+                        let line_number = record.position().map_or(0, |p| p.line()) + first_line - 1;
+                        let field = |name: &str| headers.iter()
+                            .position(|h| h.trim() == name)
+                            .and_then(|i| record.get(i))
+                            .map(|v| v.trim().to_string());
+                        (line_number, parse_row(field, options))
+                    },
+                    Err(e) => (e.position().map_or(0, |p| p.line()) + first_line - 1, Err(e.to_string())),
+                };
+                handle(line_number, row, &mut batch)?;
+            }
+        },
+        InputFormat::Ndjson => {
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row = serde_json::from_str::<serde_json::Map<String, Value>>(&line)
+                    .map_err(|e| e.to_string())
+                    .and_then(|object| {
+                        let field = |name: &str| match object.get(name) {
+                            Some(Value::String(s)) => Some(s.trim().to_string()),
+                            Some(Value::Null) | None => None,
+                            Some(v) => Some(v.to_string()),
+                        };
+                        parse_row(field, options)
+                    });
+                handle(first_line + i as u64, row, &mut batch)?;
+            }
+        },
+    }
+
+    if !batch.is_empty() {
+        importer.insert_batch(&batch)?;
+    }
+    let result = importer.finish()?;
+
+    let summary = format!(
+        "imported into {}: {} inserted, {} replaced, {} conflicts kept, {} in archived days left out, {} skipped",
+        options.source, result.inserted, result.replaced, result.conflicts, result.archived, skipped,
+    );
+    info!("{}", summary);
+    println!("{}", summary);
+
+    Ok(result)
+}
+
+/// Validates one row and returns it as an observation, or the reason it is skipped
+///
+/// # Arguments
+///
+/// * 'field' - returns the value of the named column, if any
+/// * 'options' - column mapping and formats
+fn parse_row(field: impl Fn(&str) -> Option<String>, options: &ImportOptions) -> Result<ImportRow, String> {
+    let time = options.time_columns.iter()
+        .map(|c| field(c).filter(|v| !v.is_empty()).ok_or(format!("missing {}", c)))
+        .collect::<Result<Vec<String>, String>>()?
+        .join(" ");
+    let timestamp = parse_time(&time, &options.time_format, options.time_zone)?.timestamp();
+
+    let temperature = field(&options.temperature_column)
+        .filter(|v| !v.is_empty())
+        .ok_or(format!("missing {}", options.temperature_column))
+        .and_then(|v| parse_number(&v))?;
+    if !(MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&temperature) {
+        return Err(format!("temperature out of range: {}", temperature));
+    }
+
+    let humidity = optional_number(&field, options.humidity_column.as_deref())?
+        .map(|h| match h {
+            h if (0.0..=100.0).contains(&h) => Ok(h.round() as u8),
+            h => Err(format!("humidity out of range: {}", h)),
+        })
+        .transpose()?;
+
+    let perceived_temperature = optional_number(&field, options.perceived_column.as_deref())?;
+
+    Ok(ImportRow { timestamp, temperature, humidity, perceived_temperature })
+}
+
+/// Parses an optional column, empty values are regarded as missing
+///
+/// # Arguments
+///
+/// * 'field' - returns the value of the named column, if any
+/// * 'column' - name of the column, if mapped
+fn optional_number(field: &impl Fn(&str) -> Option<String>, column: Option<&str>) -> Result<Option<f64>, String> {
+    column
+        .and_then(field)
+        .filter(|v| !v.is_empty())
+        .map(|v| parse_number(&v))
+        .transpose()
+}
+
+/// Parses a number, accepting a decimal comma as used in swedish exports
+///
+/// # Arguments
+///
+/// * 'value' - the number as text
+fn parse_number(value: &str) -> Result<f64, String> {
+    value.replace(',', ".").parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or(format!("invalid number: {}", value))
+}
+
+/// Parses a timestamp in the given format. Times without an offset are regarded as local to the given
+/// time zone, where ambiguous times during DST transitions resolve to the earliest alternative.
+///
+/// # Arguments
+///
+/// * 'time' - the timestamp as text
+/// * 'format' - timestamp format
+/// * 'tz' - time zone of times without offset
+fn parse_time(time: &str, format: &TimeFormat, tz: Tz) -> Result<DateTime<Utc>, String> {
+    let invalid = || format!("invalid time: {}", time);
+
+    let naive = match format {
+        TimeFormat::Rfc3339 => return DateTime::parse_from_rfc3339(time)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|_| invalid()),
+        TimeFormat::Unix => return time.parse::<i64>().ok()
+            .and_then(|ts| DateTime::from_timestamp(ts, 0))
+            .ok_or_else(invalid),
+        TimeFormat::Pattern(pattern) => {
+            if let Ok(dt) = DateTime::parse_from_str(time, pattern) {
+                return Ok(dt.with_timezone(&Utc));
+            }
+            NaiveDateTime::parse_from_str(time, pattern)
+                .or_else(|_| NaiveDate::parse_from_str(time, pattern).map(|d| d.and_hms_opt(0, 0, 0).unwrap()))
+                .map_err(|_| invalid())?
+        },
+    };
+
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok_or(format!("non-existent local time: {}", time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn options(input: &str, format: InputFormat) -> ImportOptions {
+        ImportOptions {
+            source: "east_west".to_string(),
+            input: input.to_string(),
+            format,
+            delimiter: b',',
+            skip_lines: 0,
+            time_columns: vec!["datetime".to_string()],
+            time_format: TimeFormat::Rfc3339,
+            time_zone: chrono_tz::UTC,
+            temperature_column: "temperature".to_string(),
+            humidity_column: Some("humidity".to_string()),
+            perceived_column: None,
+            replace: false,
+            batch_size: 2,
+        }
+    }
+
+    /// Returns paths of an input file with the given content and of a database, removed again by `remove`
+    fn files(name: &str, content: &str) -> (String, String) {
+        let path = |suffix: &str| std::env::temp_dir()
+            .join(format!("weatherlogger-import-{}-{}.{}", name, std::process::id(), suffix))
+            .to_string_lossy()
+            .to_string();
+        let (input, db_path) = (path("txt"), path("db"));
+        remove(&input, &db_path);
+        std::fs::write(&input, content).unwrap();
+
+        (input, db_path)
+    }
+
+    fn remove(input: &str, db_path: &str) {
+        for path in [input.to_string(), db_path.to_string(), format!("{}-wal", db_path), format!("{}-shm", db_path)] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn observations(db_path: &str) -> Vec<(i64, f64, Option<u8>)> {
+        let conn = Connection::open(db_path).unwrap();
+        let mut stmt = conn.prepare("SELECT datetime, temperature, humidity FROM observation ORDER BY datetime").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i64, f64, Option<u8>)>, rusqlite::Error>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_numbers_with_decimal_comma() {
+        assert_eq!(parse_number("-3.5"), Ok(-3.5));
+        assert_eq!(parse_number("-3,5"), Ok(-3.5));
+        assert!(parse_number("").is_err());
+        assert!(parse_number("warm").is_err());
+        assert!(parse_number("NaN").is_err());
+        assert!(parse_number("inf").is_err());
+    }
+
+    #[test]
+    fn parses_each_time_format() {
+        let stockholm = chrono_tz::Europe::Stockholm;
+        let expected = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        assert_eq!(parse_time("2023-11-14T22:13:20Z", &TimeFormat::Rfc3339, stockholm), Ok(expected));
+        assert_eq!(parse_time("2023-11-14T23:13:20+01:00", &TimeFormat::Rfc3339, chrono_tz::UTC), Ok(expected));
+        assert_eq!(parse_time("1700000000", &TimeFormat::Unix, stockholm), Ok(expected));
+
+        let pattern = TimeFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string());
+        assert_eq!(parse_time("2023-11-14 23:13:20", &pattern, stockholm), Ok(expected));
+        assert_eq!(parse_time("2023-11-14 22:13:20", &pattern, chrono_tz::UTC), Ok(expected));
+
+        let with_offset = TimeFormat::Pattern("%Y-%m-%d %H:%M:%S %z".to_string());
+        assert_eq!(parse_time("2023-11-14 22:13:20 +0000", &with_offset, stockholm), Ok(expected));
+
+        let date = TimeFormat::Pattern("%Y-%m-%d".to_string());
+        assert_eq!(parse_time("2023-11-14", &date, stockholm).unwrap().to_rfc3339(), "2023-11-13T23:00:00+00:00");
+
+        assert!(parse_time("2023-11-14", &TimeFormat::Rfc3339, stockholm).is_err());
+        assert!(parse_time("yesterday", &TimeFormat::Unix, stockholm).is_err());
+        assert!(parse_time("2023-03-26 02:30:00", &pattern, stockholm).is_err());
+    }
+
+    #[test]
+    fn parses_and_validates_rows() {
+        let mut options = options("", InputFormat::Csv);
+        options.time_columns = vec!["date".to_string(), "time".to_string()];
+        options.time_format = TimeFormat::Pattern("%Y-%m-%d %H:%M".to_string());
+        let row = |values: [(&'static str, &'static str); 4]| move |name: &str| values.iter()
+            .find(|(column, _)| *column == name)
+            .map(|(_, value)| value.to_string());
+
+        let parsed = parse_row(row([("date", "2023-11-14"), ("time", "22:00"), ("temperature", "-1,5"), ("humidity", "80.4")]), &options).unwrap();
+        assert_eq!((parsed.timestamp, parsed.temperature, parsed.humidity), (1_699_999_200, -1.5, Some(80)));
+
+        let parsed = parse_row(row([("date", "2023-11-14"), ("time", "22:00"), ("temperature", "4"), ("humidity", "")]), &options).unwrap();
+        assert_eq!(parsed.humidity, None);
+
+        assert_eq!(parse_row(row([("date", "2023-11-14"), ("time", ""), ("temperature", "4"), ("humidity", "")]), &options).unwrap_err(), "missing time");
+        assert_eq!(parse_row(row([("date", "2023-11-14"), ("time", "22:00"), ("temperature", ""), ("humidity", "")]), &options).unwrap_err(), "missing temperature");
+        assert!(parse_row(row([("date", "2023-11-14"), ("time", "22:00"), ("temperature", "99"), ("humidity", "")]), &options).is_err());
+        assert!(parse_row(row([("date", "2023-11-14"), ("time", "22:00"), ("temperature", "4"), ("humidity", "101")]), &options).is_err());
+    }
+
+    #[test]
+    fn imports_csv() {
+        let content = "station: east_west\ndatetime;temperature;humidity\n1970-01-01T00:00:00Z;1,5;50\nbroken;2;\n1970-01-01T00:10:00Z;2,5;\n1970-01-01T00:20:00Z;3;60\n";
+        let (input, db_path) = files("csv", content);
+        let mut options = options(&input, InputFormat::Csv);
+        options.delimiter = b';';
+        options.skip_lines = 1;
+
+        let result = import(&db_path, &options).unwrap();
+        let imported = observations(&db_path);
+        remove(&input, &db_path);
+
+        assert_eq!(result.inserted, 3);
+        assert_eq!(imported, [(0, 1.5, Some(50)), (600, 2.5, None), (1200, 3.0, Some(60))]);
+    }
+
+    #[test]
+    fn imports_ndjson() {
+        let content = "{\"datetime\": 0, \"temperature\": 1.5, \"humidity\": 50}\n\n{\"datetime\": \"600\", \"temperature\": \"2,5\", \"humidity\": null}\nnot json\n";
+        let (input, db_path) = files("ndjson", content);
+        let mut options = options(&input, InputFormat::Ndjson);
+        options.time_format = TimeFormat::Unix;
+
+        let result = import(&db_path, &options).unwrap();
+        let imported = observations(&db_path);
+        remove(&input, &db_path);
+
+        assert_eq!(result.inserted, 2);
+        assert_eq!(imported, [(0, 1.5, Some(50)), (600, 2.5, None)]);
+    }
+
+    #[test]
+    fn fails_on_columns_missing_from_header() {
+        let (input, db_path) = files("header", "datetime,temp,humidity\n1970-01-01T00:00:00Z,1.5,50\n");
+
+        let result = import(&db_path, &options(&input, InputFormat::Csv));
+        remove(&input, &db_path);
+
+        assert!(result.unwrap_err().0.contains("column temperature not found"));
+    }
+}
@@ -0,0 +1,709 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write as IoWrite};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use reqwest::{header, StatusCode};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use crate::errors::InfluxError;
+use crate::initialization::InfluxDB;
+use crate::manager_db::errors::DBError;
+use crate::manager_db::export::Export;
+use crate::manager_db::models::{ForecastRecord, MinMax, Observation, SourceStats, Temperature};
+use crate::manager_db::rollups::{BucketAggregate, Resolution};
+use crate::manager_db::storage::Storage;
+use crate::manager_db::DB;
+
+/// Name of the file holding lines not yet accepted by InfluxDB
+const BUFFER_FILE: &str = "influxdb-buffer.lp";
+
+/// Number of lines waiting to be batched before new ones are dropped, so that inserts never wait for InfluxDB
+const QUEUE_SIZE: usize = 10_000;
+
+/// Timeout of each write request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time before InfluxDB is tried again after the first failure, doubled with each further failure
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Max time between tries while InfluxDB is unavailable
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Measurement of observations
+const OBSERVATION_MEASUREMENT: &str = "observation";
+
+/// Measurement of forecasts
+const FORECAST_MEASUREMENT: &str = "forecast";
+
+/// Wraps the given storage so that every inserted observation and forecast record is mirrored to InfluxDB,
+/// and starts the task writing to InfluxDB
+///
+/// # Arguments
+///
+/// * 'db' - storage to wrap
+/// * 'config' - InfluxDB configuration
+pub fn mirror(db: DB, config: &InfluxDB) -> DB {
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    let writer = InfluxWriter::new(config);
+    let interval = Duration::from_secs(config.flush_interval_secs.max(1));
+
+    tokio::spawn(async move {
+        run_influx_writer(rx, writer, interval).await;
+    });
+
+    Arc::new(InfluxMirror { inner: db, tx })
+}
+
+/// Writer loop, lines are sent when a batch is full or at the flush interval, whichever comes first
+///
+/// # Arguments
+///
+/// * 'rx' - receiver of lines in line protocol
+/// * 'writer' - the writer
+/// * 'interval' - flush interval
+async fn run_influx_writer(mut rx: mpsc::Receiver<String>, mut writer: InfluxWriter, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some(line) => {
+                    if writer.push(line) {
+                        writer.flush().await;
+                    }
+                },
+                None => {
+                    writer.flush().await;
+                    break;
+                },
+            },
+            _ = ticker.tick() => writer.flush().await,
+        }
+    }
+}
+
+/// Storage mirroring inserted records to InfluxDB, everything else is passed on to the wrapped storage
+struct InfluxMirror {
+    inner: DB,
+    tx: mpsc::Sender<String>,
+}
+
+impl InfluxMirror {
+    /// Queues a line for the writer, the line is dropped if the queue is full
+    ///
+    /// # Arguments
+    ///
+    /// * 'line' - the line, if there was anything to write
+    fn send(&self, line: Option<String>) {
+        if let Some(line) = line {
+            if let Err(e) = self.tx.try_send(line) {
+                warn!("influxdb queue unavailable, dropping point: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for InfluxMirror {
+
+    async fn insert_observation_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        humidity: Option<u8>,
+        perceived_temp: Option<f64>,
+    ) -> Result<(), DBError> {
+        self.inner.insert_observation_record(source, date_time, temp, humidity, perceived_temp).await?;
+        self.send(observation_line(source, date_time, temp, humidity, perceived_temp));
+
+        Ok(())
+    }
+
+    async fn insert_forecast_record(
+        &self,
+        source: &str,
+        date_time: DateTime<Utc>,
+        temp: f64,
+        wind_speed: Option<f64>,
+        humidity: Option<u8>,
+        lcc_mean: Option<u8>,
+        mcc_mean: Option<u8>,
+        hcc_mean: Option<u8>,
+        symbol_code: Option<u8>,
+        precipitation: Option<f64>,
+    ) -> Result<(), DBError> {
+        self.inner.insert_forecast_record(
+            source, date_time, temp, wind_speed, humidity, lcc_mean, mcc_mean, hcc_mean, symbol_code, precipitation,
+        ).await?;
+        self.send(line(
+            FORECAST_MEASUREMENT,
+            source,
+            &[
+                ("temperature", Some(Field::Float(temp))),
+                ("wind_speed", wind_speed.map(Field::Float)),
+                ("humidity", humidity.map(|v| Field::Integer(v.into()))),
+                ("lcc_mean", lcc_mean.map(|v| Field::Integer(v.into()))),
+                ("mcc_mean", mcc_mean.map(|v| Field::Integer(v.into()))),
+                ("hcc_mean", hcc_mean.map(|v| Field::Integer(v.into()))),
+                ("symbol_code", symbol_code.map(|v| Field::Integer(v.into()))),
+                ("precipitation", precipitation.map(Field::Float)),
+            ],
+            date_time,
+        ));
+
+        Ok(())
+    }
+
+    async fn temperature_history(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Temperature, DBError> {
+        self.inner.temperature_history(source, from, to, resolution).await
+    }
+
+    async fn rollup_buckets(
+        &self,
+        source: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Resolution,
+    ) -> Result<Vec<BucketAggregate>, DBError> {
+        self.inner.rollup_buckets(source, from, to, resolution).await
+    }
+
+    async fn forecast_records(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, DBError> {
+        self.inner.forecast_records(source, from, to).await
+    }
+
+    async fn min_max(&self, source: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Option<MinMax>, DBError> {
+        self.inner.min_max(source, from, to).await
+    }
+
+    async fn latest_observations(&self, source: Option<&str>) -> Result<Vec<Observation>, DBError> {
+        self.inner.latest_observations(source).await
+    }
+
+    async fn source_stats(&self) -> Result<Vec<SourceStats>, DBError> {
+        self.inner.source_stats().await
+    }
+
+    async fn has_source(&self, source: &str) -> Result<bool, DBError> {
+        self.inner.has_source(source).await
+    }
+
+    async fn apply_retention(&self) {
+        self.inner.apply_retention().await
+    }
+
+    async fn backup(&self, dest: PathBuf) -> Result<(), DBError> {
+        self.inner.backup(dest).await
+    }
+
+    fn supports_export(&self) -> bool {
+        self.inner.supports_export()
+    }
+
+    async fn export(&self, export_request: Export, out: Box<dyn IoWrite + Send>) -> Result<u64, DBError> {
+        self.inner.export(export_request, out).await
+    }
+
+    async fn interpolated_forecast(&self, source: &str, date_time: DateTime<Utc>) -> Result<Option<ForecastRecord>, DBError> {
+        self.inner.interpolated_forecast(source, date_time).await
+    }
+}
+
+/// Sends lines in line protocol to an InfluxDB write endpoint in batches.
+///
+/// Batches that can't be written since the endpoint is unreachable, or answers with a server error or
+/// 429, are appended to a buffer file and retried, oldest first, before anything new is sent. Batches
+/// rejected as invalid are dropped, since they would never be accepted.
+///
+/// After a failure InfluxDB isn't tried again until a retry delay has passed, which grows with each
+/// failure, and meanwhile everything goes straight to the buffer. The buffer is sent one batch at a time
+/// from the offset of the first line not yet accepted, and removed once all of it is accepted.
+/// Lines accepted before a restart are sent again, which InfluxDB treats as overwrites.
+pub struct InfluxWriter {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    batch_size: usize,
+    buffer_path: PathBuf,
+    max_buffer_bytes: u64,
+    pending: Vec<String>,
+    /// Whether the buffer file may hold lines, so that it isn't looked for while InfluxDB is available
+    buffered: bool,
+    /// Offset in the buffer file of the first line not yet accepted
+    buffer_offset: u64,
+    retry_delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl InfluxWriter {
+    /// Creates a new writer, timestamps are written with second precision
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - InfluxDB configuration
+    pub fn new(config: &InfluxDB) -> Self {
+        let url = if config.url.contains("precision=") {
+            config.url.clone()
+        } else if config.url.contains('?') {
+            format!("{}&precision=s", config.url)
+        } else {
+            format!("{}?precision=s", config.url)
+        };
+
+        let buffer_path = PathBuf::from(&config.buffer_dir).join(BUFFER_FILE);
+
+        InfluxWriter {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default(),
+            url,
+            token: config.token.clone(),
+            batch_size: config.batch_size.max(1),
+            buffered: buffer_path.exists(),
+            buffer_path,
+            max_buffer_bytes: config.max_buffer_mb * 1024 * 1024,
+            pending: Vec::new(),
+            buffer_offset: 0,
+            retry_delay: MIN_RETRY_DELAY,
+            retry_at: None,
+        }
+    }
+
+    /// Adds a line to the current batch, returns true if the batch is full
+    ///
+    /// # Arguments
+    ///
+    /// * 'line' - the line in line protocol
+    pub fn push(&mut self, line: String) -> bool {
+        self.pending.push(line);
+
+        self.pending.len() >= self.batch_size
+    }
+
+    /// Sends buffered lines and then the current batch. Whatever can't be sent is appended to the buffer file,
+    /// as is everything while waiting to retry after a failure.
+    ///
+    pub async fn flush(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending);
+
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            self.append_to_buffer(pending).await;
+            return;
+        }
+
+        if let Err(e) = self.replay_buffer().await {
+            self.back_off(pending.len(), e);
+            self.append_to_buffer(pending).await;
+            return;
+        }
+
+        for (i, batch) in pending.chunks(self.batch_size).enumerate() {
+            if let Err(e) = self.send(batch).await {
+                let unsent = pending.split_off(i * self.batch_size);
+                self.back_off(unsent.len(), e);
+                self.append_to_buffer(unsent).await;
+                return;
+            }
+        }
+
+        self.retry_delay = MIN_RETRY_DELAY;
+        self.retry_at = None;
+    }
+
+    /// Schedules the next try after a failure, doubling the delay up to `MAX_RETRY_DELAY`
+    ///
+    /// # Arguments
+    ///
+    /// * 'points' - number of points about to be buffered
+    /// * 'error' - the failure
+    fn back_off(&mut self, points: usize, error: InfluxError) {
+        warn!("influxdb unavailable, buffering {} points and retrying in {}s: {}", points, self.retry_delay.as_secs(), error);
+
+        self.retry_at = Some(Instant::now() + self.retry_delay);
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    /// Sends the lines in the buffer file one batch at a time, and removes the file once all are accepted
+    ///
+    async fn replay_buffer(&mut self) -> Result<(), InfluxError> {
+        if !self.buffered {
+            return Ok(());
+        }
+
+        let mut replayed = 0;
+        loop {
+            let (path, offset, batch_size) = (self.buffer_path.clone(), self.buffer_offset, self.batch_size);
+            let (lines, next_offset) = tokio::task::spawn_blocking(move || read_batch(&path, offset, batch_size))
+                .await
+                .map_err(|e| InfluxError(e.to_string()))??;
+
+            if lines.is_empty() {
+                break;
+            }
+
+            self.send(&lines).await?;
+            self.buffer_offset = next_offset;
+            replayed += lines.len();
+        }
+
+        let path = self.buffer_path.clone();
+        tokio::task::spawn_blocking(move || match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }).await.map_err(|e| InfluxError(e.to_string()))??;
+
+        self.buffered = false;
+        self.buffer_offset = 0;
+        if replayed > 0 {
+            info!("sent {} buffered points to influxdb", replayed);
+        }
+
+        Ok(())
+    }
+
+    /// Sends one batch. Batches rejected by InfluxDB are logged and dropped, Err means the batch should be retried.
+    ///
+    /// # Arguments
+    ///
+    /// * 'lines' - lines in line protocol
+    async fn send(&self, lines: &[String]) -> Result<(), InfluxError> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut request = self.client.post(&self.url)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(lines.join("\n"));
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            Ok(())
+        } else if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Err(InfluxError(format!("influxdb responded {}", status)))
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            error!("influxdb rejected {} points with {}: {}", lines.len(), status, body);
+            Ok(())
+        }
+    }
+
+    /// Appends lines to the buffer file on the blocking thread pool, unless the lines not yet accepted
+    /// have reached the max size of the buffer
+    ///
+    /// # Arguments
+    ///
+    /// * 'lines' - lines in line protocol
+    async fn append_to_buffer(&mut self, lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+
+        self.buffered = true;
+        let (path, offset, max_bytes) = (self.buffer_path.clone(), self.buffer_offset, self.max_buffer_bytes);
+
+        let result = tokio::task::spawn_blocking(move || {
+            let size = fs::metadata(&path).map_or(0, |m| m.len());
+            if size.saturating_sub(offset) >= max_bytes {
+                error!("influxdb buffer is full, dropping {} points", lines.len());
+                return Ok(());
+            }
+
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| OpenOptions::new().create(true).append(true).open(&path))
+                .and_then(|mut file| file.write_all((lines.join("\n") + "\n").as_bytes()))
+                .map_err(|e| format!("failed to buffer {} influxdb points: {}", lines.len(), e))
+        }).await;
+
+        match result {
+            Ok(Ok(())) => {},
+            Ok(Err(e)) => error!("{}", e),
+            Err(e) => error!("failed to buffer influxdb points: {}", e),
+        }
+    }
+}
+
+/// Reads up to a batch of lines from the buffer file, returns the lines and the offset following them.
+/// No lines are returned if the file doesn't exist.
+///
+/// # Arguments
+///
+/// * 'path' - path of the buffer file
+/// * 'offset' - offset of the first line to read
+/// * 'batch_size' - max number of lines
+fn read_batch(path: &Path, offset: u64, batch_size: usize) -> Result<(Vec<String>, u64), InfluxError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), offset)),
+        Err(e) => return Err(e.into()),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut reader = BufReader::new(file);
+    let mut lines = Vec::new();
+    let mut next_offset = offset;
+    let mut line = String::new();
+    while lines.len() < batch_size {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        next_offset += read as u64;
+
+        let trimmed = line.trim_end();
+        if !trimmed.is_empty() {
+            lines.push(trimmed.to_string());
+        }
+    }
+
+    Ok((lines, next_offset))
+}
+
+/// Value of a field in line protocol
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Float(f64),
+    Integer(i64),
+}
+
+/// Returns an observation in line protocol
+///
+/// # Arguments
+///
+/// * 'source' - sensor id (source)
+/// * 'date_time' - time of the observation
+/// * 'temp' - temperature
+/// * 'humidity' - humidity
+/// * 'perceived_temp' - perceived temperature
+fn observation_line(source: &str, date_time: DateTime<Utc>, temp: f64, humidity: Option<u8>, perceived_temp: Option<f64>) -> Option<String> {
+    line(
+        OBSERVATION_MEASUREMENT,
+        source,
+        &[
+            ("temperature", Some(Field::Float(temp))),
+            ("humidity", humidity.map(|v| Field::Integer(v.into()))),
+            ("perceived_temperature", perceived_temp.map(Field::Float)),
+        ],
+        date_time,
+    )
+}
+
+/// Returns a point in line protocol with the source as tag, fields without value are left out.
+/// None is returned if there are no fields with values.
+///
+/// # Arguments
+///
+/// * 'measurement' - name of the measurement
+/// * 'source' - source, used as tag
+/// * 'fields' - field names and values
+/// * 'date_time' - time of the point, written in seconds
+fn line(measurement: &str, source: &str, fields: &[(&str, Option<Field>)], date_time: DateTime<Utc>) -> Option<String> {
+    let fields = fields.iter()
+        .filter_map(|(name, value)| match value {
+            Some(Field::Float(v)) if v.is_finite() => Some(format!("{}={}", name, v)),
+            Some(Field::Integer(v)) => Some(format!("{}={}i", name, v)),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    Some(format!("{},source={} {} {}", measurement, escape(source), fields.join(","), date_time.timestamp()))
+}
+
+/// Escapes a tag value for line protocol
+///
+/// # Arguments
+///
+/// * 'value' - the tag value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::sync::Mutex;
+    use axum::extract::State;
+    use axum::routing::post;
+    use axum::Router;
+
+    /// Stub of an InfluxDB write endpoint, recording accepted bodies and answering with a settable status
+    #[derive(Clone, Default)]
+    struct Stub {
+        status: Arc<AtomicU16>,
+        received: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn write(State(stub): State<Stub>, body: String) -> StatusCode {
+        let status = StatusCode::from_u16(stub.status.load(Ordering::SeqCst)).unwrap();
+        if status.is_success() {
+            stub.received.lock().unwrap().push(body);
+        }
+        status
+    }
+
+    async fn start_stub() -> (Stub, String) {
+        let stub = Stub::default();
+        stub.status.store(204, Ordering::SeqCst);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v2/write?org=home&bucket=weather", listener.local_addr().unwrap());
+        let app = Router::new().route("/api/v2/write", post(write)).with_state(stub.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (stub, url)
+    }
+
+    fn writer(url: &str, name: &str) -> InfluxWriter {
+        let buffer_dir = std::env::temp_dir().join(format!("weatherlogger-influx-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&buffer_dir);
+
+        InfluxWriter::new(&InfluxDB {
+            url: url.to_string(),
+            token: Some("secret".to_string()),
+            batch_size: 2,
+            flush_interval_secs: 1,
+            buffer_dir: buffer_dir.display().to_string(),
+            max_buffer_mb: 1,
+        })
+    }
+
+    fn time(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn formats_line_protocol() {
+        assert_eq!(
+            observation_line("east west,1", time(1767225600), -3.5, Some(80), None).unwrap(),
+            "observation,source=east\\ west\\,1 temperature=-3.5,humidity=80i 1767225600",
+        );
+        assert_eq!(
+            line(FORECAST_MEASUREMENT, "smhi", &[("temperature", Some(Field::Float(f64::NAN))), ("symbol_code", None)], time(0)),
+            None,
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_batches_to_endpoint() {
+        let (stub, url) = start_stub().await;
+        let mut writer = writer(&url, "batches");
+        assert!(writer.url.ends_with("&precision=s"));
+
+        assert!(!writer.push("a v=1 1".to_string()));
+        assert!(writer.push("a v=2 2".to_string()));
+        writer.push("a v=3 3".to_string());
+        writer.flush().await;
+
+        assert_eq!(*stub.received.lock().unwrap(), vec!["a v=1 1\na v=2 2", "a v=3 3"]);
+        assert!(!writer.buffer_path.exists());
+    }
+
+    #[tokio::test]
+    async fn buffers_on_disk_while_endpoint_fails() {
+        let (stub, url) = start_stub().await;
+        let mut writer = writer(&url, "buffer");
+
+        stub.status.store(503, Ordering::SeqCst);
+        writer.push("a v=1 1".to_string());
+        writer.flush().await;
+        writer.push("a v=2 2".to_string());
+        writer.flush().await;
+
+        assert!(stub.received.lock().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(&writer.buffer_path).unwrap(), "a v=1 1\na v=2 2\n");
+
+        // Nothing is sent until the retry delay has passed
+        stub.status.store(204, Ordering::SeqCst);
+        writer.push("a v=3 3".to_string());
+        writer.flush().await;
+        assert!(stub.received.lock().unwrap().is_empty());
+
+        writer.retry_at = Some(Instant::now());
+        writer.flush().await;
+
+        assert_eq!(*stub.received.lock().unwrap(), vec!["a v=1 1\na v=2 2", "a v=3 3"]);
+        assert!(!writer.buffer_path.exists());
+    }
+
+    #[test]
+    fn reads_buffer_one_batch_at_a_time() {
+        let path = std::env::temp_dir().join(format!("weatherlogger-influx-batches-{}.lp", std::process::id()));
+        fs::write(&path, "a v=1 1\na v=2 2\n\na v=3 3\n").unwrap();
+
+        let (first, offset) = read_batch(&path, 0, 2).unwrap();
+        let (second, offset) = read_batch(&path, offset, 2).unwrap();
+        let (third, _) = read_batch(&path, offset, 2).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(first, vec!["a v=1 1", "a v=2 2"]);
+        assert_eq!(second, vec!["a v=3 3"]);
+        assert!(third.is_empty());
+        assert!(read_batch(&path, 0, 2).unwrap().0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn buffers_on_disk_while_endpoint_is_unreachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=weather", listener.local_addr().unwrap());
+        drop(listener);
+        let mut writer = writer(&url, "unreachable");
+
+        writer.push("a v=1 1".to_string());
+        writer.flush().await;
+
+        assert_eq!(fs::read_to_string(&writer.buffer_path).unwrap(), "a v=1 1\n");
+    }
+
+    #[tokio::test]
+    async fn drops_rejected_batches() {
+        let (stub, url) = start_stub().await;
+        let mut writer = writer(&url, "rejected");
+
+        stub.status.store(400, Ordering::SeqCst);
+        writer.push("not line protocol".to_string());
+        writer.flush().await;
+
+        assert!(stub.received.lock().unwrap().is_empty());
+        assert!(!writer.buffer_path.exists());
+    }
+
+    #[tokio::test]
+    async fn mirrors_observations_with_the_stored_time() {
+        let path = std::env::temp_dir().join(format!("weatherlogger-influx-mirror-{}.db", std::process::id()));
+        let config: crate::initialization::DB = toml::from_str(&format!("db_path = {:?}\nmax_age_in_days = 30", path.display().to_string())).unwrap();
+        let inner = crate::manager_db::open(&config, vec![]).await.unwrap();
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let mirror = InfluxMirror { inner, tx };
+        let date_time = Utc::now() - chrono::TimeDelta::minutes(10);
+
+        mirror.insert_observation_record("east_west", date_time, 4.5, None, None).await.unwrap();
+        let stored = mirror.latest_observations(Some("east_west")).await.unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        assert_eq!(stored[0].date_time.timestamp(), date_time.timestamp());
+        assert_eq!(rx.recv().await.unwrap(), format!("observation,source=east_west temperature=4.5 {}", date_time.timestamp()));
+    }
+}
@@ -3,13 +3,16 @@ mod models;
 
 use std::ops::Add;
 use std::time::Duration;
-use chrono::{DateTime, DurationRound, Utc, TimeDelta};
+use chrono::{DateTime, Utc, TimeDelta};
+use chrono_tz::Tz;
 use reqwest::Client;
+use crate::local_time::local_date;
 use crate::manager_smhi::errors::SMHIError;
 use crate::manager_smhi::models::{ForecastValues, FullForecast};
 
 
 /// Struct for managing whether forecasts produced by SMHI
+#[allow(clippy::upper_case_acronyms)]
 pub struct SMHI {
     client: Client,
     lat: f64,
@@ -40,20 +43,23 @@ impl SMHI {
 
     /// Retrieves a weather forecast from SMHI for the given date.
     /// The raw forecast consists of several days worth of data and many weather parameters,
-    /// but the returned forecast will only include the specified date (plus one) and data
-    /// representing cloud index (0-8) and forecasted temperatures.
+    /// but the returned forecast will only include the specified date (plus the following days
+    /// up to the given number of days) and data representing cloud index (0-8), forecasted
+    /// temperatures, wind, humidity, precipitation and weather symbol.
     ///
     /// # Arguments
     ///
     /// * 'date_time' - the date to get a forecast for
-    pub async fn new_forecast(&self, date_time: DateTime<Utc>) -> Result<Vec<ForecastValues>, SMHIError> {
+    /// * 'days' - number of days, starting with the given date, to include
+    /// * 'tz' - time zone of the calendar days
+    pub async fn new_forecast(&self, date_time: DateTime<Utc>, days: i64, tz: Tz) -> Result<Vec<ForecastValues>, SMHIError> {
         let smhi_domain = "https://opendata-download-metfcst.smhi.se";
         let base_url = "/api/category/snow1g/version/1/geotype/point";
         let url = format!("{}{}/lon/{:0.4}/lat/{:0.4}/data.json",
                           smhi_domain, base_url, self.long, self.lat);
 
-        let date = date_time.duration_trunc(TimeDelta::days(1)).unwrap();
-        let next_date = date.add(TimeDelta::days(1));
+        let date = local_date(date_time, tz);
+        let end_date = date.add(TimeDelta::days(days));
 
         let req = self.client
             .get(url)
@@ -70,8 +76,8 @@ impl SMHI {
         let mut forecast: Vec<ForecastValues> = Vec::new();
 
         for ts in tmp_forecast.time_series {
-            let forecast_date = ts.time.duration_trunc(TimeDelta::days(1)).unwrap();
-            if forecast_date == date || forecast_date == next_date {
+            let forecast_date = local_date(ts.time, tz);
+            if forecast_date >= date && forecast_date < end_date {
                 let time_values = ForecastValues {
                     valid_time: ts.time,
                     temp: ts.data.air_temperature,
@@ -81,14 +87,15 @@ impl SMHI {
                     mcc_mean: ts.data.medium_type_cloud_area_fraction,
                     hcc_mean: ts.data.high_type_cloud_area_fraction,
                     symbol_code: ts.data.symbol_code.round() as u8,
+                    precipitation: ts.data.precipitation_amount_mean,
                 };
 
                 forecast.push(time_values);
             }
         }
        
-        if forecast.len() == 0 {
-            Err(SMHIError::SMHI(format!("No forecast found for {}", date_time.date_naive())))
+        if forecast.is_empty() {
+            Err(SMHIError::SMHI(format!("No forecast found for {}", date)))
         } else {
             Ok(forecast)
         }
@@ -10,6 +10,7 @@ pub struct Data {
     pub medium_type_cloud_area_fraction: u8,
     pub high_type_cloud_area_fraction: u8,
     pub symbol_code: f64,
+    pub precipitation_amount_mean: Option<f64>,
 }
 
 
@@ -36,4 +37,5 @@ pub struct ForecastValues {
     pub mcc_mean: u8,
     pub hcc_mean: u8,
     pub symbol_code: u8,
+    pub precipitation: Option<f64>,
 }
\ No newline at end of file
@@ -1,11 +1,11 @@
-use std::sync::Arc;
-use chrono::Utc;
+use chrono::{SubsecRound, Utc};
 use log::{error, info, warn};
 use serde::Deserialize;
-use tokio::sync::Mutex;
 use tokio::task::JoinSet;
 use crate::errors::TempError;
+use crate::events::{publish, Event, Events};
 use crate::manager_db::DB;
+use crate::manager_db::models::Observation;
 use crate::perceived_temperature::perceived_temperature;
 
 #[derive(Deserialize)]
@@ -18,9 +18,11 @@ struct Data {
 /// # Arguments
 ///
 /// * 'db' - database to store readings into
+/// * 'events' - sender of events about inserted readings
 /// * 'sensor' - a vector of sensors to read
 /// * 'name' - the name of the sensor
-pub async fn run_observations(db: Arc<Mutex<DB>>, sensor: &Vec<String>, name: &str) {
+/// * 'forecast' - the name of the forecast to get wind and humidity from
+pub async fn run_observations(db: DB, events: Events, sensor: &[String], name: &str, forecast: &str) {
     let mut last_inserted: f64 = 0.0;
 
     loop {
@@ -33,19 +35,19 @@ pub async fn run_observations(db: Arc<Mutex<DB>>, sensor: &Vec<String>, name: &s
 
         let result = set.join_all().await;
         let mut temperature: Option<f64> = None;
-        for reading in result.into_iter() {
-            if let Ok(r) = reading {
-                info!("temperature: {}", r);
-                match &mut temperature {
-                    Some(t) => *t = t.min(r),
-                    t @ _ => *t = Some(r),
-                }
+        for r in result.into_iter().flatten() {
+            info!("temperature: {}", r);
+            match &mut temperature {
+                Some(t) => *t = t.min(r),
+                t => *t = Some(r),
             }
         }
 
         if let Some(t) = temperature {
             if t != last_inserted {
-                let wsh = db.lock().await.get_wind_and_humidity("smhi", Utc::now());
+                // Stored in whole seconds, so the event carries the same time as the stored observation
+                let now = Utc::now().trunc_subsecs(0);
+                let wsh = db.get_wind_and_humidity(forecast, now).await;
                 match wsh {
                     Ok(wsh) => {
                         let pt = if let Some((ws, h)) = wsh {
@@ -55,8 +57,15 @@ pub async fn run_observations(db: Arc<Mutex<DB>>, sensor: &Vec<String>, name: &s
                             None
                         };
 
-                        if let Err(e) = db.lock().await.insert_observation_record(name, t, None, pt) {
-                            error!("error while inserting data in database: {}", e);
+                        match db.insert_observation_record(name, now, t, None, pt).await {
+                            Ok(()) => publish(&events, Event::Observation(Observation {
+                                source: name.to_string(),
+                                date_time: now,
+                                temperature: Some(t),
+                                humidity: None,
+                                perceived_temperature: pt,
+                            })),
+                            Err(e) => error!("error while inserting data in database: {}", e),
                         }
                     },
                     Err(e) => {
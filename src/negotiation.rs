@@ -0,0 +1,443 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::Value;
+use crate::errors::ApiError;
+use crate::manager_db::models::{
+    BucketedTemperature, CurrentConditions, DailyForecast, DataItem, Forecast, ForecastRecord, ForecastSummary, MinMax, MinMaxPeriod, Source, SourceKind,
+    Temperature, TemperatureStats, Trend,
+};
+use crate::statistics::Stat;
+use crate::units::Units;
+
+/// Response formats that can be requested with the `Accept` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentType {
+    Json,
+    Csv,
+    MessagePack,
+}
+
+impl ContentType {
+    /// Returns the preferred supported format of the `Accept` header, json if the header is missing.
+    /// Media ranges are tried in order of their quality value, and those with q=0 are never chosen.
+    ///
+    /// # Arguments
+    ///
+    /// * 'headers' - request headers
+    pub fn negotiate(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let Some(accept) = headers.get(header::ACCEPT) else {
+            return Ok(ContentType::Json);
+        };
+        let accept = accept.to_str()
+            .map_err(|_| ApiError::NotAcceptable("invalid Accept header".to_string()))?;
+
+        let mut ranges = accept.split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next()?.to_ascii_lowercase();
+                let quality = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(1.0, |q| q.parse::<f32>().unwrap_or(0.0));
+
+                Some((media_type, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect::<Vec<(String, f32)>>();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges.iter()
+            .find_map(|(media_type, _)| ContentType::from_media_type(media_type))
+            .ok_or(ApiError::NotAcceptable(format!(
+                "none of '{}' is supported, use application/json, text/csv or application/msgpack", accept,
+            )))
+    }
+
+    /// Returns the format of a media type or range, if supported
+    ///
+    /// # Arguments
+    ///
+    /// * 'media_type' - lower case media type without parameters
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(ContentType::Json),
+            "text/csv" | "text/*" => Some(ContentType::Csv),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(ContentType::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the Content-Type header for the format
+    ///
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::Csv => "text/csv; charset=utf-8",
+            ContentType::MessagePack => "application/msgpack",
+        }
+    }
+}
+
+/// Serializes a response body in the negotiated format. If the units of the values are given their labels
+/// are added to json and MessagePack bodies, see `with_units`.
+///
+/// # Arguments
+///
+/// * 'content_type' - the negotiated format
+/// * 'value' - what to respond with
+/// * 'units' - units of the values
+pub fn respond<T: Serialize + ToCsv>(content_type: ContentType, value: &T, units: Option<&Units>) -> Result<Response, ApiError> {
+    let labelled = units.map(|units| with_units(value, units)).transpose()?;
+
+    let body = match content_type {
+        ContentType::Json => match &labelled {
+            Some(labelled) => serde_json::to_vec_pretty(labelled),
+            None => serde_json::to_vec_pretty(value),
+        }.map_err(|e| ApiError::Internal(e.to_string()))?,
+        ContentType::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            value.to_csv(&mut writer).map_err(|e| ApiError::Internal(e.to_string()))?;
+            writer.into_inner().map_err(|e| ApiError::Internal(e.to_string()))?
+        },
+        ContentType::MessagePack => match &labelled {
+            Some(labelled) => rmp_serde::to_vec_named(labelled),
+            None => rmp_serde::to_vec_named(value),
+        }.map_err(|e| ApiError::Internal(e.to_string()))?,
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, HeaderValue::from_static(content_type.mime())),
+        (header::VARY, HeaderValue::from_static("Accept")),
+    ];
+
+    Ok((headers, body).into_response())
+}
+
+/// Returns the value with the unit labels added as 'units', to the value itself if it is an object
+/// or to each of its items if it is a list
+///
+/// # Arguments
+///
+/// * 'value' - the value
+/// * 'units' - units of the values
+pub fn with_units<T: Serialize>(value: &T, units: &Units) -> Result<Value, ApiError> {
+    let labels = serde_json::to_value(units).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let mut value = serde_json::to_value(value).map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    match &mut value {
+        Value::Object(map) => {
+            map.insert("units".to_string(), labels);
+        },
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                if let Value::Object(map) = item {
+                    map.insert("units".to_string(), labels.clone());
+                }
+            }
+        },
+        _ => (),
+    }
+
+    Ok(value)
+}
+
+/// Something that is written as a CSV table with a header row
+pub trait ToCsv {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error>;
+}
+
+/// Something that is written as one row of a CSV table
+pub trait CsvRecord {
+    const HEADER: &'static [&'static str];
+
+    fn record(&self) -> Vec<String>;
+}
+
+impl<T: CsvRecord> ToCsv for Vec<T> {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        writer.write_record(T::HEADER)?;
+        for item in self {
+            writer.write_record(item.record())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Nothing gives a table with the header only
+impl<T: CsvRecord> ToCsv for Option<T> {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        writer.write_record(T::HEADER)?;
+        if let Some(item) = self {
+            writer.write_record(item.record())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Only the history is written, the current and perceived temperature are left to the other formats
+impl ToCsv for Temperature {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        self.history.to_csv(writer)
+    }
+}
+
+/// One column per requested statistic
+impl ToCsv for BucketedTemperature {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        let mut header = vec!["datetime"];
+        header.extend(self.stats.iter().map(Stat::name));
+        writer.write_record(header)?;
+
+        for bucket in &self.buckets {
+            let mut record = vec![bucket.x.to_rfc3339()];
+            record.extend(self.stats.iter().map(|stat| field(stat_value(bucket, *stat))));
+            writer.write_record(record)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ToCsv for Forecast {
+    fn to_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error> {
+        match self {
+            Forecast::Records(records) => records.to_csv(writer),
+            Forecast::Summaries(summaries) => summaries.to_csv(writer),
+        }
+    }
+}
+
+impl CsvRecord for DataItem<f64> {
+    const HEADER: &'static [&'static str] = &["datetime", "temperature"];
+
+    fn record(&self) -> Vec<String> {
+        vec![self.x.to_rfc3339(), self.y.to_string()]
+    }
+}
+
+impl CsvRecord for MinMax {
+    const HEADER: &'static [&'static str] = &["min", "min_time", "max", "max_time"];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.min.to_string(),
+            field(self.min_time.map(|t| t.to_rfc3339())),
+            self.max.to_string(),
+            field(self.max_time.map(|t| t.to_rfc3339())),
+        ]
+    }
+}
+
+impl CsvRecord for MinMaxPeriod {
+    const HEADER: &'static [&'static str] = &["date", "min", "min_time", "max", "max_time"];
+
+    fn record(&self) -> Vec<String> {
+        let mut record = vec![self.date.to_string()];
+        record.extend(self.min_max.record());
+
+        record
+    }
+}
+
+impl CsvRecord for ForecastRecord {
+    const HEADER: &'static [&'static str] = &[
+        "datetime", "temperature", "wind_speed", "humidity", "lcc_mean", "mcc_mean", "hcc_mean",
+        "symbol_code", "precipitation", "symbol_icon", "symbol_description",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.date_time.to_rfc3339(),
+            field(self.temperature),
+            field(self.wind_speed),
+            field(self.humidity),
+            field(self.lcc_mean),
+            field(self.mcc_mean),
+            field(self.hcc_mean),
+            field(self.symbol_code),
+            field(self.precipitation),
+            field(self.symbol.as_ref().map(|s| &s.icon)),
+            field(self.symbol.as_ref().map(|s| s.description_en)),
+        ]
+    }
+}
+
+impl CsvRecord for ForecastSummary {
+    const HEADER: &'static [&'static str] = &[
+        "datetime", "min_temperature", "max_temperature", "max_wind_speed", "total_precipitation",
+        "mean_humidity", "mean_lcc", "mean_mcc", "mean_hcc", "symbol_code", "symbol_icon", "symbol_description",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.date_time.to_rfc3339(),
+            field(self.min_temperature),
+            field(self.max_temperature),
+            field(self.max_wind_speed),
+            field(self.total_precipitation),
+            field(self.mean_humidity),
+            field(self.mean_lcc),
+            field(self.mean_mcc),
+            field(self.mean_hcc),
+            field(self.symbol_code),
+            field(self.symbol.as_ref().map(|s| &s.icon)),
+            field(self.symbol.as_ref().map(|s| s.description_en)),
+        ]
+    }
+}
+
+impl CsvRecord for DailyForecast {
+    const HEADER: &'static [&'static str] = &[
+        "date", "min_temperature", "max_temperature", "total_precipitation", "max_wind_speed",
+        "symbol_code", "symbol_icon", "symbol_description",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.date.to_string(),
+            field(self.min_temperature),
+            field(self.max_temperature),
+            field(self.total_precipitation),
+            field(self.max_wind_speed),
+            field(self.symbol_code),
+            field(self.symbol.as_ref().map(|s| &s.icon)),
+            field(self.symbol.as_ref().map(|s| s.description_en)),
+        ]
+    }
+}
+
+impl CsvRecord for CurrentConditions {
+    const HEADER: &'static [&'static str] = &[
+        "source", "datetime", "age_secs", "temperature", "humidity", "perceived_temperature", "trend",
+        "today_min", "today_max", "wind_speed", "lcc_mean", "mcc_mean", "hcc_mean", "symbol_code", "precipitation",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        let fc = self.forecast.as_ref();
+
+        vec![
+            self.source.clone(),
+            self.date_time.to_rfc3339(),
+            self.age_secs.to_string(),
+            field(self.temperature),
+            field(self.humidity),
+            field(self.perceived_temperature),
+            field(self.trend.map(|t| match t {
+                Trend::Rising => "rising",
+                Trend::Falling => "falling",
+                Trend::Steady => "steady",
+            })),
+            field(self.today.as_ref().map(|t| t.min)),
+            field(self.today.as_ref().map(|t| t.max)),
+            field(fc.and_then(|fc| fc.wind_speed)),
+            field(fc.and_then(|fc| fc.lcc_mean)),
+            field(fc.and_then(|fc| fc.mcc_mean)),
+            field(fc.and_then(|fc| fc.hcc_mean)),
+            field(fc.and_then(|fc| fc.symbol_code)),
+            field(fc.and_then(|fc| fc.precipitation)),
+        ]
+    }
+}
+
+impl CsvRecord for Source {
+    const HEADER: &'static [&'static str] = &["id", "kind", "name", "configured", "lat", "long", "first", "last", "count"];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            match self.kind {
+                SourceKind::Observation => "observation",
+                SourceKind::Forecast => "forecast",
+            }.to_string(),
+            field(self.name.as_ref()),
+            self.configured.to_string(),
+            field(self.location.map(|l| l.lat)),
+            field(self.location.map(|l| l.long)),
+            field(self.first.map(|t| t.to_rfc3339())),
+            field(self.last.map(|t| t.to_rfc3339())),
+            self.count.to_string(),
+        ]
+    }
+}
+
+/// Returns the value of a statistic of a bucket
+///
+/// # Arguments
+///
+/// * 'bucket' - the bucket
+/// * 'stat' - the statistic
+fn stat_value(bucket: &TemperatureStats, stat: Stat) -> Option<f64> {
+    match stat {
+        Stat::Min => bucket.min,
+        Stat::Max => bucket.max,
+        Stat::Mean => bucket.mean,
+        Stat::Median => bucket.median,
+        Stat::P10 => bucket.p10,
+        Stat::P90 => bucket.p90,
+    }
+}
+
+/// Returns an optional value as a CSV field, empty if there is no value
+///
+/// # Arguments
+///
+/// * 'value' - the value
+fn field<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> Result<ContentType, ApiError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+
+        ContentType::negotiate(&headers)
+    }
+
+    #[test]
+    fn defaults_to_json_without_accept_header() {
+        assert_eq!(ContentType::negotiate(&HeaderMap::new()).unwrap(), ContentType::Json);
+    }
+
+    #[test]
+    fn chooses_the_highest_quality_supported_format() {
+        assert_eq!(negotiate("text/csv").unwrap(), ContentType::Csv);
+        assert_eq!(negotiate("Application/MsgPack").unwrap(), ContentType::MessagePack);
+        assert_eq!(negotiate("application/json;q=0.5, text/csv;q=0.8").unwrap(), ContentType::Csv);
+        assert_eq!(negotiate("text/html, application/x-msgpack;q=0.9, */*;q=0.1").unwrap(), ContentType::MessagePack);
+    }
+
+    #[test]
+    fn keeps_header_order_for_equal_quality() {
+        assert_eq!(negotiate("text/csv, application/json").unwrap(), ContentType::Csv);
+        assert_eq!(negotiate("application/json; q=0.5, text/csv; q=0.5").unwrap(), ContentType::Json);
+    }
+
+    #[test]
+    fn resolves_wildcards() {
+        assert_eq!(negotiate("*/*").unwrap(), ContentType::Json);
+        assert_eq!(negotiate("application/*").unwrap(), ContentType::Json);
+        assert_eq!(negotiate("text/*").unwrap(), ContentType::Csv);
+        assert_eq!(negotiate("text/html, text/*;q=0.5").unwrap(), ContentType::Csv);
+    }
+
+    #[test]
+    fn never_chooses_excluded_formats() {
+        assert_eq!(negotiate("application/json;q=0, text/csv;q=0.1").unwrap(), ContentType::Csv);
+        assert_eq!(negotiate("application/json;q=invalid, text/csv;q=0.1").unwrap(), ContentType::Csv);
+        assert!(matches!(negotiate("application/json;q=0"), Err(ApiError::NotAcceptable(_))));
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        let e = negotiate("text/html, image/png").unwrap_err();
+
+        assert!(matches!(e, ApiError::NotAcceptable(_)));
+        assert!(e.into_message().contains("text/html, image/png"));
+    }
+}
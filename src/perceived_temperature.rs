@@ -52,9 +52,9 @@ fn heat_index(temp: f64, humidity: f64) -> f64 {
             0.00085282 * temp * humidity * humidity -
             0.00000199 * temp * temp * humidity * humidity;
 
-        if humidity < 13.0 && temp >= 80.0 && temp <= 112.0 {
+        if humidity < 13.0 && (80.0..=112.0).contains(&temp) {
             heat_index -= ((13.0 - humidity) / 4.0) * ((17.0 - (temp - 95.0).abs()) / 17.0).sqrt();
-        } else if humidity > 85.0 && temp >= 80.0 && temp <= 87.0 {
+        } else if humidity > 85.0 && (80.0..=87.0).contains(&temp) {
             heat_index +=  ((humidity - 85.0) / 10.0) * ((87.0 - temp) / 5.0);
         }
     }
@@ -67,7 +67,7 @@ fn heat_index(temp: f64, humidity: f64) -> f64 {
 /// # Arguments
 ///
 /// * 'temp' - temperature in Celsius
-fn celsius_to_fahrenheit(temp: f64) -> f64 {
+pub fn celsius_to_fahrenheit(temp: f64) -> f64 {
     temp * 1.8 + 32.0
 }
 
@@ -85,6 +85,6 @@ fn fahrenheit_to_celsius(temp: f64) -> f64 {
 /// # Arguments
 ///
 /// * 'mps' - meter per second
-fn mps_to_mph(mps: f64) -> f64 {
+pub fn mps_to_mph(mps: f64) -> f64 {
     mps * (1.0 / 1.609344 * 3.6)
 }
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use chrono::{DateTime, DurationRound, NaiveDate, TimeDelta, Utc};
+use chrono_tz::Tz;
+use crate::interpolation::interpolate_at;
+use crate::local_time::start_of_day;
+use crate::manager_db::models::{DailyForecast, ForecastRecord, ForecastSummary};
+
+/// Largest step of a grid, in days
+pub const MAX_STEP_DAYS: i64 = 366;
+
+/// Max number of grid points of one request
+pub const MAX_GRID_POINTS: i64 = 10_000;
+
+/// How forecast records are to be mapped onto a regular time grid
+#[derive(Clone, Copy, Debug)]
+pub enum Resampling {
+    /// One forecast record per grid point, interpolated from the surrounding records
+    Interpolate(TimeDelta),
+    /// One summary per grid step, aggregated from the records within the step
+    Aggregate(TimeDelta),
+}
+
+impl Resampling {
+    /// Creates a resampling from query parameters
+    ///
+    /// # Arguments
+    ///
+    /// * 'step' - grid step, e.g. '15m', '1h' or '1d'
+    /// * 'agg' - 'interp' (default) or 'summary'
+    pub fn from_params(step: &str, agg: Option<&str>) -> Result<Self, String> {
+        let step = parse_step(step).ok_or(format!("invalid step: {}, use e.g. 15m, 1h or 1d up to {}d", step, MAX_STEP_DAYS))?;
+
+        match agg.unwrap_or("interp") {
+            "interp" => Ok(Resampling::Interpolate(step)),
+            "summary" => Ok(Resampling::Aggregate(step)),
+            agg => Err(format!("invalid agg: {}", agg)),
+        }
+    }
+
+    /// Returns the grid step
+    ///
+    pub fn step(&self) -> TimeDelta {
+        match self {
+            Resampling::Interpolate(step) | Resampling::Aggregate(step) => *step,
+        }
+    }
+}
+
+/// Parses a step given as a positive number followed by a unit (s, m, h or d), of at most MAX_STEP_DAYS
+///
+/// # Arguments
+///
+/// * 'step' - the step to parse, e.g. '15m'
+pub fn parse_step(step: &str) -> Option<TimeDelta> {
+    let split = step.len().checked_sub(1)?;
+    let (value, unit) = step.split_at_checked(split)?;
+    let value: i64 = value.parse().ok().filter(|v| *v > 0)?;
+
+    let step = match unit {
+        "s" => TimeDelta::try_seconds(value),
+        "m" => TimeDelta::try_minutes(value),
+        "h" => TimeDelta::try_hours(value),
+        "d" => TimeDelta::try_days(value),
+        _ => None,
+    }?;
+
+    (step <= TimeDelta::days(MAX_STEP_DAYS)).then_some(step)
+}
+
+/// Returns forecast records interpolated at every grid point between the given boundaries.
+/// Grid points are aligned to whole multiples of the step (in UTC), and grid points not covered
+/// by the given records are left out.
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+/// * 'from' - start of the grid
+/// * 'to' - end of the grid (non-inclusive)
+/// * 'step' - grid step
+pub fn resample(records: &[ForecastRecord], from: DateTime<Utc>, to: DateTime<Utc>, step: TimeDelta) -> Vec<ForecastRecord> {
+    grid(from, to, step)
+        .filter_map(|t| interpolate_at(records, t))
+        .collect()
+}
+
+/// Returns one summary per grid step between the given boundaries, where each summary aggregates
+/// the forecast records within the step. Steps without records are left out.
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+/// * 'from' - start of the grid
+/// * 'to' - end of the grid (non-inclusive)
+/// * 'step' - grid step
+pub fn aggregate(records: &[ForecastRecord], from: DateTime<Utc>, to: DateTime<Utc>, step: TimeDelta) -> Vec<ForecastSummary> {
+    aggregate_periods(records, grid(from, to, step).map_while(|t| Some((t, t.checked_add_signed(step)?))))
+}
+
+/// Returns one summary per local calendar day from `from` up to and including `to`, where each
+/// summary aggregates the forecast records within that day. Days without records are left out.
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+/// * 'from' - first local date
+/// * 'to' - last local date
+/// * 'tz' - time zone defining the local calendar days
+pub fn aggregate_daily(records: &[ForecastRecord], from: NaiveDate, to: NaiveDate, tz: Tz) -> Vec<DailyForecast> {
+    let days = from.iter_days().take_while(|d| *d <= to).collect::<Vec<NaiveDate>>();
+    let periods = days.iter().map_while(|d| Some((start_of_day(*d, tz), start_of_day(d.checked_add_signed(TimeDelta::days(1))?, tz))));
+
+    aggregate_periods(records, periods)
+        .into_iter()
+        .map(|s| DailyForecast {
+            date: s.date_time.with_timezone(&tz).date_naive(),
+            min_temperature: s.min_temperature,
+            max_temperature: s.max_temperature,
+            total_precipitation: s.total_precipitation,
+            max_wind_speed: s.max_wind_speed,
+            symbol_code: s.symbol_code,
+            symbol: None,
+        })
+        .collect()
+}
+
+/// Returns one summary per given period, periods without records are left out
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+/// * 'periods' - start and (non-inclusive) end of each period
+fn aggregate_periods(records: &[ForecastRecord], periods: impl Iterator<Item = (DateTime<Utc>, DateTime<Utc>)>) -> Vec<ForecastSummary> {
+    let amounts = precipitation_amounts(records);
+
+    periods
+        .filter_map(|(from, to)| {
+            let start = records.partition_point(|r| r.date_time < from);
+            let end = records.partition_point(|r| r.date_time < to);
+
+            summarize(&records[start..end], &amounts[start..end], from)
+        })
+        .collect()
+}
+
+/// Summarizes the given forecast records, returns None if there are none
+///
+/// # Arguments
+///
+/// * 'records' - forecast records to summarize
+/// * 'amounts' - precipitation amount for each record
+/// * 'date_time' - the time the summary is for
+fn summarize(records: &[ForecastRecord], amounts: &[Option<f64>], date_time: DateTime<Utc>) -> Option<ForecastSummary> {
+    if records.is_empty() {
+        return None;
+    }
+
+    let temperatures = records.iter().filter_map(|r| r.temperature);
+
+    Some(ForecastSummary {
+        date_time,
+        min_temperature: temperatures.clone().reduce(f64::min),
+        max_temperature: temperatures.reduce(f64::max),
+        max_wind_speed: records.iter().filter_map(|r| r.wind_speed).reduce(f64::max),
+        total_precipitation: amounts.iter().flatten().copied().reduce(|a, b| a + b),
+        mean_humidity: mean(records.iter().filter_map(|r| r.humidity.map(f64::from))),
+        mean_lcc: mean(records.iter().filter_map(|r| r.lcc_mean.map(f64::from))),
+        mean_mcc: mean(records.iter().filter_map(|r| r.mcc_mean.map(f64::from))),
+        mean_hcc: mean(records.iter().filter_map(|r| r.hcc_mean.map(f64::from))),
+        symbol_code: dominant_symbol(records),
+        symbol: None,
+    })
+}
+
+/// Returns the precipitation amount in mm for each record.
+/// Forecasted precipitation is a mean intensity in mm/h over the period leading up to the
+/// record, which is one hour for the first record and the time since the previous record otherwise.
+///
+/// # Arguments
+///
+/// * 'records' - forecast records ordered by time
+fn precipitation_amounts(records: &[ForecastRecord]) -> Vec<Option<f64>> {
+    let mut previous: Option<DateTime<Utc>> = None;
+
+    records.iter()
+        .map(|r| {
+            let hours = previous.map_or(1.0, |p| (r.date_time - p).num_seconds() as f64 / 3600.0);
+            previous = Some(r.date_time);
+
+            r.precipitation.map(|p| p * hours)
+        })
+        .collect()
+}
+
+/// Returns the most frequent symbol code, where ties are resolved in favour of the higher
+/// (generally more severe) code
+///
+/// # Arguments
+///
+/// * 'records' - forecast records
+fn dominant_symbol(records: &[ForecastRecord]) -> Option<u8> {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for code in records.iter().filter_map(|r| r.symbol_code) {
+        *counts.entry(code).or_default() += 1;
+    }
+
+    counts.into_iter()
+        .max_by_key(|(code, count)| (*count, *code))
+        .map(|(code, _)| code)
+}
+
+/// Returns the mean of the given values, or None if there are no values
+///
+/// # Arguments
+///
+/// * 'values' - values to calculate mean for
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+
+    if count > 0 { Some(sum / count as f64) } else { None }
+}
+
+/// Returns an iterator over grid points aligned to whole multiples of step, ending early should the
+/// grid reach the end of representable time
+///
+/// # Arguments
+///
+/// * 'from' - start of the grid, rounded up to the nearest grid point
+/// * 'to' - end of the grid (non-inclusive)
+/// * 'step' - grid step
+fn grid(from: DateTime<Utc>, to: DateTime<Utc>, step: TimeDelta) -> impl Iterator<Item = DateTime<Utc>> {
+    let start = from.duration_trunc(step).unwrap_or(from);
+    let start = if start < from { start.checked_add_signed(step) } else { Some(start) };
+
+    std::iter::successors(start, move |t| t.checked_add_signed(step))
+        .take_while(move |t| *t < to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps_up_to_max() {
+        assert_eq!(parse_step("15m"), Some(TimeDelta::minutes(15)));
+        assert_eq!(parse_step("366d"), Some(TimeDelta::days(366)));
+        assert_eq!(parse_step("367d"), None);
+        assert_eq!(parse_step("100000000d"), None);
+        assert_eq!(parse_step("0h"), None);
+        assert_eq!(parse_step("h"), None);
+    }
+
+    #[test]
+    fn grid_ends_at_end_of_representable_time() {
+        let step = TimeDelta::days(MAX_STEP_DAYS);
+        let from = DateTime::<Utc>::MAX_UTC - TimeDelta::days(1000);
+
+        let points = grid(from, DateTime::<Utc>::MAX_UTC, step).count();
+
+        assert!(points <= 3);
+        assert!(aggregate(&[], from, DateTime::<Utc>::MAX_UTC, step).is_empty());
+    }
+}
@@ -0,0 +1,403 @@
+use chrono::{DateTime, Days, DurationRound, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use crate::local_time::{local_date, start_of_day};
+use crate::manager_db::models::{DataItem, TemperatureStats};
+use crate::manager_db::rollups::BucketAggregate;
+
+/// Max number of buckets of one request
+pub const MAX_BUCKETS: i64 = 10_000;
+
+/// Statistics that can be requested for a bucket of observations
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stat {
+    Min,
+    Max,
+    Mean,
+    Median,
+    P10,
+    P90,
+}
+
+impl Stat {
+    /// Parses a comma separated list of statistics, e.g. 'min,max,mean'
+    ///
+    /// # Arguments
+    ///
+    /// * 'stats' - the list to parse
+    pub fn parse_list(stats: &str) -> Result<Vec<Stat>, String> {
+        let mut result = Vec::new();
+
+        for stat in stats.split(',').map(str::trim) {
+            let stat = match stat {
+                "min" => Stat::Min,
+                "max" => Stat::Max,
+                "mean" => Stat::Mean,
+                "median" => Stat::Median,
+                "p10" => Stat::P10,
+                "p90" => Stat::P90,
+                stat => return Err(format!("invalid stat: {}", stat)),
+            };
+            if !result.contains(&stat) {
+                result.push(stat);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the name of the statistic as given in requests
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stat::Min => "min",
+            Stat::Max => "max",
+            Stat::Mean => "mean",
+            Stat::Median => "median",
+            Stat::P10 => "p10",
+            Stat::P90 => "p90",
+        }
+    }
+}
+
+/// A period of time and the temperatures during it, either a raw temperature holding until the next one or
+/// the summary of a rollup bucket
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    /// Start of the time covered
+    pub start: DateTime<Utc>,
+    /// End of the time covered (non-inclusive)
+    pub end: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    /// Time-weighted mean, see `BucketAggregate::mean`
+    pub mean: f64,
+}
+
+/// Returns a span per raw temperature, holding until the next one or the given time
+///
+/// # Arguments
+///
+/// * 'history' - temperatures ordered by time
+/// * 'until' - when the last temperature stops holding
+pub fn history_spans(history: &[DataItem<f64>], until: DateTime<Utc>) -> Vec<Span> {
+    history.iter()
+        .enumerate()
+        .map(|(i, item)| Span {
+            start: item.x,
+            end: history.get(i + 1).map_or(until, |n| n.x),
+            min: item.y,
+            max: item.y,
+            mean: item.y,
+        })
+        .collect()
+}
+
+/// Returns the spans of consecutive rollup buckets. A bucket covers the time from its start, or from its first
+/// observation if no temperature is held from before it, to its end or now. Since buckets without observations
+/// aren't stored, the last temperature of a bucket holds until the next bucket starts, or until the given time
+/// after the last bucket.
+///
+/// # Arguments
+///
+/// * 'buckets' - the buckets and their sizes in seconds, in ascending order
+/// * 'held' - the last temperature before the buckets and when it starts holding, if any
+/// * 'until' - when the last temperature stops holding, e.g. when the raw observations start
+/// * 'now' - the current time
+pub fn rollup_spans(buckets: &[(BucketAggregate, i64)], held: Option<(DateTime<Utc>, f64)>, until: DateTime<Utc>, now: DateTime<Utc>) -> Vec<Span> {
+    let mut result = Vec::new();
+    let (mut held_since, mut held) = (held.map(|(since, _)| since), held.map(|(_, temp)| temp));
+    let hold = |result: &mut Vec<Span>, since: Option<DateTime<Utc>>, next: DateTime<Utc>, temp: Option<f64>| {
+        if let (Some(since), Some(temp)) = (since, temp) {
+            if since < next.min(now) {
+                result.push(Span { start: since, end: next.min(now), min: temp, max: temp, mean: temp });
+            }
+        }
+    };
+
+    for (b, size) in buckets {
+        let (Some(start), Some(end)) = (
+            DateTime::from_timestamp(b.bucket, 0),
+            b.bucket.checked_add(*size).and_then(|end| DateTime::from_timestamp(end, 0)),
+        ) else {
+            continue;
+        };
+        hold(&mut result, held_since, start, held);
+
+        // The held temperature counts until the first one of the bucket
+        let mean = b.mean(*size, held, now.timestamp());
+        let (min, max) = match held {
+            Some(temp) if b.first_time > b.bucket => (b.min.min(temp), b.max.max(temp)),
+            _ => (b.min, b.max),
+        };
+        let first = if held.is_some() { Some(start) } else { DateTime::from_timestamp(b.first_time, 0) };
+        if let Some(first) = first {
+            result.push(Span { start: first, end: end.min(now), min, max, mean });
+        }
+        (held_since, held) = (Some(end), Some(b.last));
+    }
+    hold(&mut result, held_since, until, held);
+
+    result
+}
+
+/// Returns the requested statistics of each bucket between the given boundaries.
+///
+/// The sensor only records changes, so each temperature holds until the next one and the statistics are
+/// weighted by how long each temperature held: the mean is the time-weighted mean and the median and
+/// percentiles are those of the time spent at each temperature. Buckets of whole days are aligned to local
+/// midnight in the given time zone, so they are 23 or 25 hours long on DST transitions, and shorter buckets
+/// to whole multiples of the bucket size (in UTC). Buckets without any temperature are left out.
+///
+/// Spans of rollups are of lower precision than the raw temperatures: each counts as its mean temperature held
+/// for the time it covers, and its min and max count in every bucket it overlaps.
+///
+/// # Arguments
+///
+/// * 'spans' - raw temperatures and rollups, not overlapping each other
+/// * 'from' - start of the range
+/// * 'end' - end of the range (non-inclusive)
+/// * 'bucket' - bucket size, one bucket for the whole range if None
+/// * 'tz' - time zone to align buckets of whole days in
+/// * 'stats' - statistics to calculate
+pub fn bucket_stats(
+    spans: &[Span],
+    from: DateTime<Utc>,
+    end: DateTime<Utc>,
+    bucket: Option<TimeDelta>,
+    tz: Tz,
+    stats: &[Stat],
+) -> Vec<TemperatureStats> {
+    let mut spans = spans.iter()
+        .map(|s| Span { start: s.start.max(from), end: s.end.min(end), ..*s })
+        .filter(|s| s.start < s.end)
+        .collect::<Vec<Span>>();
+    spans.sort_by_key(|s| s.start);
+
+    let mut first = 0;
+    periods(from, end, bucket, tz).into_iter()
+        .filter_map(|(start, stop)| {
+            while first < spans.len() && spans[first].end <= start {
+                first += 1;
+            }
+            let overlapping = spans[first..].iter()
+                .take_while(|s| s.start < stop)
+                .map(|s| (s, (s.end.min(stop) - s.start.max(start)).num_milliseconds() as f64))
+                .filter(|(_, d)| *d > 0.0)
+                .collect::<Vec<(&Span, f64)>>();
+
+            let durations = overlapping.iter().map(|(s, d)| (s.mean, *d)).collect();
+            let extremes = overlapping.iter()
+                .fold(None, |acc: Option<(f64, f64)>, (s, _)| Some(acc.map_or((s.min, s.max), |(min, max)| (min.min(s.min), max.max(s.max)))));
+
+            summarize(start, durations, extremes, stats)
+        })
+        .collect()
+}
+
+/// Returns the buckets between the given boundaries, see `bucket_stats`. Buckets that would end past the
+/// latest representable time are left out.
+///
+/// # Arguments
+///
+/// * 'from' - start of the range
+/// * 'end' - end of the range (non-inclusive)
+/// * 'bucket' - bucket size, one bucket for the whole range if None
+/// * 'tz' - time zone to align buckets of whole days in
+fn periods(from: DateTime<Utc>, end: DateTime<Utc>, bucket: Option<TimeDelta>, tz: Tz) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    match bucket {
+        Some(size) if size.num_seconds() % 86400 == 0 => {
+            // Whole days, counted from 1970-01-01 in local time
+            let days = size.num_days();
+            let step = Days::new(days as u64);
+            let date = local_date(from, tz);
+            let offset = (date - DateTime::UNIX_EPOCH.date_naive()).num_days().rem_euclid(days);
+            let Some(first) = date.checked_sub_days(Days::new(offset as u64)) else {
+                return Vec::new();
+            };
+
+            std::iter::successors(Some(first), |d| d.checked_add_days(step))
+                .map_while(|d| Some((start_of_day(d, tz), start_of_day(d.checked_add_days(step)?, tz))))
+                .take_while(|(start, _)| *start < end)
+                .collect()
+        },
+        Some(size) => {
+            let start = from.duration_trunc(size).unwrap_or(from);
+            std::iter::successors(Some(start), |t| t.checked_add_signed(size))
+                .map_while(|t| Some((t, t.checked_add_signed(size)?)))
+                .take_while(|(start, _)| *start < end)
+                .collect()
+        },
+        None if from < end => vec![(from, end)],
+        None => Vec::new(),
+    }
+}
+
+/// Returns the requested statistics of one bucket, or None if there is no temperature in it
+///
+/// # Arguments
+///
+/// * 'x' - start of the bucket
+/// * 'durations' - temperatures and for how long each held within the bucket
+/// * 'extremes' - min and max of the spans overlapping the bucket, if any
+/// * 'stats' - statistics to calculate
+fn summarize(x: DateTime<Utc>, mut durations: Vec<(f64, f64)>, extremes: Option<(f64, f64)>, stats: &[Stat]) -> Option<TemperatureStats> {
+    let total: f64 = durations.iter().map(|(_, d)| d).sum();
+    if durations.is_empty() || total <= 0.0 {
+        return None;
+    }
+
+    durations.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let wanted = |stat: Stat| stats.contains(&stat);
+    let (min, max) = extremes.map_or((durations[0].0, durations[durations.len() - 1].0), |(min, max)| {
+        (min.min(durations[0].0), max.max(durations[durations.len() - 1].0))
+    });
+
+    Some(TemperatureStats {
+        x,
+        min: wanted(Stat::Min).then_some(min),
+        max: wanted(Stat::Max).then_some(max),
+        mean: wanted(Stat::Mean).then(|| durations.iter().map(|(v, d)| v * d).sum::<f64>() / total),
+        median: wanted(Stat::Median).then(|| quantile(&durations, total, 0.5)),
+        p10: wanted(Stat::P10).then(|| quantile(&durations, total, 0.1)),
+        p90: wanted(Stat::P90).then(|| quantile(&durations, total, 0.9)),
+    })
+}
+
+/// Returns the time-weighted quantile, i.e. the lowest temperature held for at least the given share of the time
+///
+/// # Arguments
+///
+/// * 'sorted' - temperatures and durations ordered by temperature
+/// * 'total' - total duration
+/// * 'q' - the quantile, between 0 and 1
+fn quantile(sorted: &[(f64, f64)], total: f64, q: f64) -> f64 {
+    let target = q * total;
+    let mut cumulative = 0.0;
+
+    for (value, duration) in sorted {
+        cumulative += duration;
+        if cumulative >= target {
+            return *value;
+        }
+    }
+
+    sorted[sorted.len() - 1].0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const ALL: [Stat; 6] = [Stat::Min, Stat::Max, Stat::Mean, Stat::Median, Stat::P10, Stat::P90];
+
+    fn at(hour: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(hour * 3600, 0).unwrap()
+    }
+
+    #[test]
+    fn quantile_is_weighted_by_time_held() {
+        // 1 degree for 1 hour, 2 degrees for 8 hours, 3 degrees for 1 hour
+        let sorted = [(1.0, 1.0), (2.0, 8.0), (3.0, 1.0)];
+
+        assert_eq!(quantile(&sorted, 10.0, 0.1), 1.0);
+        assert_eq!(quantile(&sorted, 10.0, 0.5), 2.0);
+        assert_eq!(quantile(&sorted, 10.0, 0.9), 2.0);
+        assert_eq!(quantile(&sorted, 10.0, 0.95), 3.0);
+        assert_eq!(quantile(&sorted, 10.0, 1.0), 3.0);
+    }
+
+    #[test]
+    fn summarizes_requested_stats() {
+        let summary = summarize(at(0), vec![(20.0, 1.0), (10.0, 3.0)], None, &ALL).unwrap();
+
+        assert_eq!(summary.min, Some(10.0));
+        assert_eq!(summary.max, Some(20.0));
+        assert_eq!(summary.mean, Some(12.5));
+        assert_eq!(summary.median, Some(10.0));
+        assert_eq!(summary.p10, Some(10.0));
+        assert_eq!(summary.p90, Some(20.0));
+
+        let summary = summarize(at(0), vec![(20.0, 1.0)], Some((-5.0, 25.0)), &[Stat::Min, Stat::Max]).unwrap();
+        assert_eq!((summary.min, summary.max, summary.mean), (Some(-5.0), Some(25.0), None));
+
+        assert!(summarize(at(0), Vec::new(), None, &ALL).is_none());
+        assert!(summarize(at(0), vec![(20.0, 0.0)], None, &ALL).is_none());
+    }
+
+    #[test]
+    fn day_buckets_follow_local_days() {
+        let tz = chrono_tz::Europe::Stockholm;
+        // DST starts on 2024-03-31, making it 23 hours long
+        let from = tz.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap().with_timezone(&Utc);
+        let end = tz.with_ymd_and_hms(2024, 4, 1, 12, 0, 0).unwrap().with_timezone(&Utc);
+        let history = [DataItem { x: from, y: 5.0 }];
+
+        let buckets = bucket_stats(&history_spans(&history, end), from, end, Some(TimeDelta::days(1)), tz, &[Stat::Mean]);
+
+        let starts = buckets.iter().map(|b| b.x.with_timezone(&tz).to_rfc3339()).collect::<Vec<String>>();
+        assert_eq!(starts, ["2024-03-30T00:00:00+01:00", "2024-03-31T00:00:00+01:00", "2024-04-01T00:00:00+02:00"]);
+        assert_eq!(periods(from, end, Some(TimeDelta::days(1)), tz)[1].1 - buckets[1].x, TimeDelta::hours(23));
+    }
+
+    #[test]
+    fn buckets_end_at_end_of_representable_time() {
+        let from = DateTime::<Utc>::MAX_UTC - TimeDelta::minutes(30);
+        let history = [DataItem { x: from, y: 5.0 }];
+
+        for bucket in [TimeDelta::hours(1), TimeDelta::days(366)] {
+            let spans = history_spans(&history, DateTime::<Utc>::MAX_UTC);
+            assert!(bucket_stats(&spans, from, DateTime::<Utc>::MAX_UTC, Some(bucket), chrono_tz::UTC, &ALL).is_empty());
+        }
+    }
+
+    #[test]
+    fn rollups_hold_last_temperature_until_next_bucket() {
+        let bucket = |hour: i64, temp: f64| BucketAggregate {
+            bucket: hour * 3600,
+            sum: temp,
+            count: 1,
+            weighted_sum: Some(0.0),
+            first_time: hour * 3600 + 1800,
+            last: temp,
+            last_time: hour * 3600 + 1800,
+            min: temp,
+            max: temp,
+        };
+
+        let buckets = [(bucket(0, 10.0), 3600), (bucket(3, 20.0), 3600)];
+
+        let spans = rollup_spans(&buckets, None, at(5), at(24)).iter().map(|s| (s.start, s.end, s.mean)).collect::<Vec<_>>();
+        assert_eq!(spans, [
+            (at(0) + TimeDelta::minutes(30), at(1), 10.0),
+            (at(1), at(3), 10.0),
+            (at(3), at(4), 15.0),
+            (at(4), at(5), 20.0),
+        ]);
+
+        // A temperature held from before the rollups fills the time until the first one
+        let spans = rollup_spans(&buckets[1..], Some((at(1), 5.0)), at(4), at(24)).iter().map(|s| (s.start, s.end, s.mean)).collect::<Vec<_>>();
+        assert_eq!(spans, [(at(1), at(3), 5.0), (at(3), at(4), 12.5)]);
+
+        let spans = rollup_spans(&[], Some((at(1), 5.0)), at(2), at(24)).iter().map(|s| (s.start, s.end, s.mean)).collect::<Vec<_>>();
+        assert_eq!(spans, [(at(1), at(2), 5.0)]);
+    }
+
+    #[test]
+    fn archived_rollups_fill_in_before_raw_history() {
+        // Hour 0 is only left as a rollup, the raw history starts at hour 1
+        let mut spans = vec![Span { start: at(0), end: at(1), min: 0.0, max: 30.0, mean: 10.0 }];
+        spans.extend(history_spans(&[DataItem { x: at(1), y: 20.0 }], at(2)));
+
+        let buckets = bucket_stats(&spans, at(0), at(2), None, chrono_tz::UTC, &ALL);
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!((buckets[0].min, buckets[0].max, buckets[0].mean), (Some(0.0), Some(30.0), Some(15.0)));
+
+        let buckets = bucket_stats(&spans, at(0), at(2), Some(TimeDelta::hours(1)), chrono_tz::UTC, &ALL);
+        assert_eq!(buckets.iter().map(|b| b.mean.unwrap()).collect::<Vec<f64>>(), [10.0, 20.0]);
+        assert_eq!(buckets[1].max, Some(20.0));
+    }
+}
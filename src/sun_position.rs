@@ -0,0 +1,114 @@
+use chrono::{DateTime, Timelike, Utc};
+
+/// Sun elevation (in degrees) at which the upper limb of the sun touches the horizon,
+/// i.e. the conventional definition of sunrise and sunset including atmospheric refraction
+pub const SUNRISE_ELEVATION: f64 = -0.833;
+
+/// Calculates the sun elevation above the horizon in degrees.
+/// The algorithm is the simplified one published by NOAA, which is accurate to within
+/// a fraction of a degree and more than enough for deciding day or night.
+/// https://gml.noaa.gov/grad/solcalc/calcdetails.html
+///
+/// # Arguments
+///
+/// * 'date_time' - date and time in UTC
+/// * 'lat' - latitude of the location
+/// * 'long' - longitude of the location
+pub fn sun_elevation(date_time: DateTime<Utc>, lat: f64, long: f64) -> f64 {
+    let julian_day = date_time.timestamp() as f64 / 86400.0 + 2440587.5;
+    let t = (julian_day - 2451545.0) / 36525.0;
+
+    let mean_long = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.0);
+    let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+    let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+
+    let center = mean_anomaly.to_radians().sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+        + (2.0 * mean_anomaly).to_radians().sin() * (0.019993 - 0.000101 * t)
+        + (3.0 * mean_anomaly).to_radians().sin() * 0.000289;
+
+    let omega = 125.04 - 1934.136 * t;
+    let apparent_long = mean_long + center - 0.00569 - 0.00478 * omega.to_radians().sin();
+
+    let mean_obliquity = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let obliquity = (mean_obliquity + 0.00256 * omega.to_radians().cos()).to_radians();
+
+    let declination = (obliquity.sin() * apparent_long.to_radians().sin()).asin();
+
+    let y = (obliquity / 2.0).tan().powi(2);
+    let l0 = mean_long.to_radians();
+    let m = mean_anomaly.to_radians();
+    let equation_of_time = 4.0 * (y * (2.0 * l0).sin()
+        - 2.0 * eccentricity * m.sin()
+        + 4.0 * eccentricity * y * m.sin() * (2.0 * l0).cos()
+        - 0.5 * y * y * (4.0 * l0).sin()
+        - 1.25 * eccentricity * eccentricity * (2.0 * m).sin()).to_degrees();
+
+    let minutes = date_time.num_seconds_from_midnight() as f64 / 60.0;
+    let true_solar_time = (minutes + equation_of_time + 4.0 * long).rem_euclid(1440.0);
+    let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+    let lat = lat.to_radians();
+    let zenith = (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0)
+        .acos();
+
+    90.0 - zenith.to_degrees()
+}
+
+/// Returns true if the sun is above the horizon at the given time and location
+///
+/// # Arguments
+///
+/// * 'date_time' - date and time in UTC
+/// * 'lat' - latitude of the location
+/// * 'long' - longitude of the location
+pub fn is_daylight(date_time: DateTime<Utc>, lat: f64, long: f64) -> bool {
+    sun_elevation(date_time, lat, long) > SUNRISE_ELEVATION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    const STOCKHOLM: (f64, f64) = (59.3293, 18.0686);
+    const TROMSO: (f64, f64) = (69.6492, 18.9553);
+
+    #[test]
+    fn sun_is_overhead_at_equator_on_equinox() {
+        let noon = utc("2026-03-20T12:07:00Z");
+
+        assert!(sun_elevation(noon, 0.0, 0.0) > 88.0);
+        assert!(sun_elevation(utc("2026-03-20T00:07:00Z"), 0.0, 0.0) < -88.0);
+    }
+
+    #[test]
+    fn noon_elevation_matches_latitude_and_declination() {
+        // Solar noon in Stockholm is about 10:27 UTC at midsummer, with the sun at 90 - 59.33 + 23.44 degrees
+        let elevation = sun_elevation(utc("2026-06-21T10:27:00Z"), STOCKHOLM.0, STOCKHOLM.1);
+
+        assert!((elevation - 54.1).abs() < 0.5, "elevation {}", elevation);
+    }
+
+    #[test]
+    fn daylight_changes_at_sunrise_and_sunset() {
+        // Sunrise in Stockholm at midsummer is 03:31 CEST (01:31 UTC) and sunset 22:08 CEST (20:08 UTC)
+        let (lat, long) = STOCKHOLM;
+
+        assert!(!is_daylight(utc("2026-06-21T01:25:00Z"), lat, long));
+        assert!(is_daylight(utc("2026-06-21T01:37:00Z"), lat, long));
+        assert!(is_daylight(utc("2026-06-21T20:02:00Z"), lat, long));
+        assert!(!is_daylight(utc("2026-06-21T20:14:00Z"), lat, long));
+    }
+
+    #[test]
+    fn midnight_sun_and_polar_night() {
+        let (lat, long) = TROMSO;
+
+        assert!(is_daylight(utc("2026-06-21T23:00:00Z"), lat, long));
+        assert!(!is_daylight(utc("2026-12-21T11:00:00Z"), lat, long));
+    }
+}
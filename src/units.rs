@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize, Serializer};
+use crate::manager_db::models::{
+    BucketedTemperature, CurrentConditions, DailyForecast, Forecast, ForecastRecord, ForecastSummary, MinMax, MinMaxPeriod, Observation,
+    Temperature,
+};
+use crate::perceived_temperature::{celsius_to_fahrenheit, mps_to_mph};
+
+/// Unit systems that can be requested with the 'units' parameter
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    #[default]
+    Metric,
+    Imperial,
+    /// The units configured in `[units.custom]`
+    Custom,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeedUnit {
+    #[default]
+    Mps,
+    Kmh,
+    Mph,
+    Knots,
+}
+
+/// Units of the values in a response, serialized as their labels
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Units {
+    #[serde(default)]
+    pub temperature: TemperatureUnit,
+    #[serde(default)]
+    pub wind_speed: SpeedUnit,
+}
+
+impl TemperatureUnit {
+    /// Converts a temperature in Celsius, as stored, to this unit
+    ///
+    /// # Arguments
+    ///
+    /// * 'celsius' - temperature in Celsius
+    pub fn convert(&self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius_to_fahrenheit(celsius),
+            TemperatureUnit::Kelvin => celsius + 273.15,
+        }
+    }
+
+    /// Returns the label of the unit as given in responses
+    ///
+    pub fn label(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+            TemperatureUnit::Kelvin => "K",
+        }
+    }
+}
+
+impl SpeedUnit {
+    /// Converts a speed in meters per second, as stored, to this unit
+    ///
+    /// # Arguments
+    ///
+    /// * 'mps' - speed in meters per second
+    pub fn convert(&self, mps: f64) -> f64 {
+        match self {
+            SpeedUnit::Mps => mps,
+            SpeedUnit::Kmh => mps * 3.6,
+            SpeedUnit::Mph => mps_to_mph(mps),
+            SpeedUnit::Knots => mps * 3.6 / 1.852,
+        }
+    }
+
+    /// Returns the label of the unit as given in responses
+    ///
+    pub fn label(&self) -> &'static str {
+        match self {
+            SpeedUnit::Mps => "m/s",
+            SpeedUnit::Kmh => "km/h",
+            SpeedUnit::Mph => "mph",
+            SpeedUnit::Knots => "kn",
+        }
+    }
+}
+
+impl Serialize for TemperatureUnit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.label())
+    }
+}
+
+impl Serialize for SpeedUnit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.label())
+    }
+}
+
+impl Units {
+    /// Returns the units of a unit system
+    ///
+    /// # Arguments
+    ///
+    /// * 'system' - the unit system
+    /// * 'custom' - the configured custom units
+    pub fn of(system: UnitSystem, custom: Units) -> Self {
+        match system {
+            UnitSystem::Metric => Units { temperature: TemperatureUnit::Celsius, wind_speed: SpeedUnit::Mps },
+            UnitSystem::Imperial => Units { temperature: TemperatureUnit::Fahrenheit, wind_speed: SpeedUnit::Mph },
+            UnitSystem::Custom => custom,
+        }
+    }
+
+    /// Converts an optional temperature in Celsius
+    ///
+    /// # Arguments
+    ///
+    /// * 'value' - temperature in Celsius
+    fn temperature(&self, value: Option<f64>) -> Option<f64> {
+        value.map(|v| self.temperature.convert(v))
+    }
+
+    /// Converts an optional wind speed in meters per second
+    ///
+    /// # Arguments
+    ///
+    /// * 'value' - wind speed in meters per second
+    fn wind_speed(&self, value: Option<f64>) -> Option<f64> {
+        value.map(|v| self.wind_speed.convert(v))
+    }
+}
+
+/// Something holding values in the stored units (Celsius and m/s) that can be converted to other units
+pub trait Convert {
+    fn convert(&mut self, units: &Units);
+}
+
+impl<T: Convert> Convert for Vec<T> {
+    fn convert(&mut self, units: &Units) {
+        self.iter_mut().for_each(|item| item.convert(units));
+    }
+}
+
+impl<T: Convert> Convert for Option<T> {
+    fn convert(&mut self, units: &Units) {
+        if let Some(item) = self {
+            item.convert(units);
+        }
+    }
+}
+
+impl Convert for Temperature {
+    fn convert(&mut self, units: &Units) {
+        self.history.iter_mut().for_each(|item| item.y = units.temperature.convert(item.y));
+        self.current_temp = units.temperature(self.current_temp);
+        self.perceived_temp = units.temperature(self.perceived_temp);
+    }
+}
+
+impl Convert for BucketedTemperature {
+    fn convert(&mut self, units: &Units) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.min = units.temperature(bucket.min);
+            bucket.max = units.temperature(bucket.max);
+            bucket.mean = units.temperature(bucket.mean);
+            bucket.median = units.temperature(bucket.median);
+            bucket.p10 = units.temperature(bucket.p10);
+            bucket.p90 = units.temperature(bucket.p90);
+        }
+        self.current_temp = units.temperature(self.current_temp);
+        self.perceived_temp = units.temperature(self.perceived_temp);
+    }
+}
+
+impl Convert for MinMax {
+    fn convert(&mut self, units: &Units) {
+        self.min = units.temperature.convert(self.min);
+        self.max = units.temperature.convert(self.max);
+    }
+}
+
+impl Convert for MinMaxPeriod {
+    fn convert(&mut self, units: &Units) {
+        self.min_max.convert(units);
+    }
+}
+
+impl Convert for ForecastRecord {
+    fn convert(&mut self, units: &Units) {
+        self.temperature = units.temperature(self.temperature);
+        self.wind_speed = units.wind_speed(self.wind_speed);
+    }
+}
+
+impl Convert for ForecastSummary {
+    fn convert(&mut self, units: &Units) {
+        self.min_temperature = units.temperature(self.min_temperature);
+        self.max_temperature = units.temperature(self.max_temperature);
+        self.max_wind_speed = units.wind_speed(self.max_wind_speed);
+    }
+}
+
+impl Convert for Forecast {
+    fn convert(&mut self, units: &Units) {
+        match self {
+            Forecast::Records(records) => records.convert(units),
+            Forecast::Summaries(summaries) => summaries.convert(units),
+        }
+    }
+}
+
+impl Convert for DailyForecast {
+    fn convert(&mut self, units: &Units) {
+        self.min_temperature = units.temperature(self.min_temperature);
+        self.max_temperature = units.temperature(self.max_temperature);
+        self.max_wind_speed = units.wind_speed(self.max_wind_speed);
+    }
+}
+
+impl Convert for Observation {
+    fn convert(&mut self, units: &Units) {
+        self.temperature = units.temperature(self.temperature);
+        self.perceived_temperature = units.temperature(self.perceived_temperature);
+    }
+}
+
+impl Convert for CurrentConditions {
+    fn convert(&mut self, units: &Units) {
+        self.temperature = units.temperature(self.temperature);
+        self.perceived_temperature = units.temperature(self.perceived_temperature);
+        self.today.convert(units);
+        self.forecast.convert(units);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn converts_temperatures() {
+        assert_close(TemperatureUnit::Celsius.convert(-3.5), -3.5);
+        assert_close(TemperatureUnit::Fahrenheit.convert(-40.0), -40.0);
+        assert_close(TemperatureUnit::Fahrenheit.convert(100.0), 212.0);
+        assert_close(TemperatureUnit::Kelvin.convert(-273.15), 0.0);
+    }
+
+    #[test]
+    fn converts_speeds() {
+        assert_close(SpeedUnit::Mps.convert(10.0), 10.0);
+        assert_close(SpeedUnit::Kmh.convert(10.0), 36.0);
+        assert_close(SpeedUnit::Mph.convert(0.44704), 1.0);
+        assert_close(SpeedUnit::Knots.convert(1852.0 / 3600.0), 1.0);
+    }
+
+    #[test]
+    fn parses_units_and_systems() {
+        let units: Units = toml::from_str("temperature = \"kelvin\"\nwind_speed = \"knots\"").unwrap();
+        assert_eq!(units, Units { temperature: TemperatureUnit::Kelvin, wind_speed: SpeedUnit::Knots });
+
+        let units: Units = toml::from_str("wind_speed = \"kmh\"").unwrap();
+        assert_eq!(units, Units { temperature: TemperatureUnit::Celsius, wind_speed: SpeedUnit::Kmh });
+
+        assert!(toml::from_str::<Units>("temperature = \"rankine\"").is_err());
+
+        assert_eq!(serde_json::from_str::<UnitSystem>("\"imperial\"").unwrap(), UnitSystem::Imperial);
+        assert!(serde_json::from_str::<UnitSystem>("\"Imperial\"").is_err());
+    }
+
+    #[test]
+    fn resolves_unit_systems() {
+        let custom = Units { temperature: TemperatureUnit::Kelvin, wind_speed: SpeedUnit::Kmh };
+
+        assert_eq!(Units::of(UnitSystem::Metric, custom), Units { temperature: TemperatureUnit::Celsius, wind_speed: SpeedUnit::Mps });
+        assert_eq!(Units::of(UnitSystem::Imperial, custom), Units { temperature: TemperatureUnit::Fahrenheit, wind_speed: SpeedUnit::Mph });
+        assert_eq!(Units::of(UnitSystem::Custom, custom), custom);
+    }
+
+    #[test]
+    fn serializes_labels() {
+        let units = Units { temperature: TemperatureUnit::Fahrenheit, wind_speed: SpeedUnit::Knots };
+
+        assert_eq!(serde_json::to_value(units).unwrap(), serde_json::json!({"temperature": "°F", "wind_speed": "kn"}));
+    }
+
+    #[test]
+    fn converts_temperatures_and_speeds_only() {
+        let units = Units { temperature: TemperatureUnit::Fahrenheit, wind_speed: SpeedUnit::Kmh };
+        let mut records = vec![ForecastRecord {
+            date_time: DateTime::from_timestamp(0, 0).unwrap(),
+            temperature: Some(10.0),
+            wind_speed: Some(5.0),
+            humidity: Some(80),
+            lcc_mean: None,
+            mcc_mean: None,
+            hcc_mean: None,
+            symbol_code: Some(3),
+            precipitation: Some(1.5),
+            symbol: None,
+        }];
+
+        records.convert(&units);
+
+        assert_eq!((records[0].temperature, records[0].wind_speed), (Some(50.0), Some(18.0)));
+        assert_eq!((records[0].humidity, records[0].precipitation), (Some(80), Some(1.5)));
+
+        let mut min_max = Some(MinMax { min: -10.0, min_time: None, max: 0.0, max_time: None });
+        min_max.convert(&units);
+        assert_eq!(min_max.map(|m| (m.min, m.max)), Some((14.0, 32.0)));
+    }
+}
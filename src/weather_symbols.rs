@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::sun_position::is_daylight;
+
+/// Static description of one SMHI Wsymb2 weather symbol
+struct Symbol {
+    description_sv: &'static str,
+    description_en: &'static str,
+    icon: &'static str,
+    day_night: bool,
+}
+
+/// SMHI Wsymb2 symbol table, index 0 corresponds to symbol code 1.
+/// https://opendata.smhi.se/apidocs/metfcst/parameters.html#parameter-wsymb
+const SYMBOLS: [Symbol; 27] = [
+    Symbol { description_sv: "Klart", description_en: "Clear sky", icon: "clear", day_night: true },
+    Symbol { description_sv: "Nästan klart", description_en: "Nearly clear sky", icon: "mostly-clear", day_night: true },
+    Symbol { description_sv: "Växlande molnighet", description_en: "Variable cloudiness", icon: "partly-cloudy", day_night: true },
+    Symbol { description_sv: "Halvklart", description_en: "Halfclear sky", icon: "half-clear", day_night: true },
+    Symbol { description_sv: "Molnigt", description_en: "Cloudy sky", icon: "cloudy", day_night: false },
+    Symbol { description_sv: "Mulet", description_en: "Overcast", icon: "overcast", day_night: false },
+    Symbol { description_sv: "Dimma", description_en: "Fog", icon: "fog", day_night: false },
+    Symbol { description_sv: "Lätta regnskurar", description_en: "Light rain showers", icon: "light-rain-showers", day_night: true },
+    Symbol { description_sv: "Måttliga regnskurar", description_en: "Moderate rain showers", icon: "rain-showers", day_night: true },
+    Symbol { description_sv: "Kraftiga regnskurar", description_en: "Heavy rain showers", icon: "heavy-rain-showers", day_night: true },
+    Symbol { description_sv: "Åskskurar", description_en: "Thunderstorm", icon: "thunder-showers", day_night: true },
+    Symbol { description_sv: "Lätta byar av regn och snö", description_en: "Light sleet showers", icon: "light-sleet-showers", day_night: true },
+    Symbol { description_sv: "Måttliga byar av regn och snö", description_en: "Moderate sleet showers", icon: "sleet-showers", day_night: true },
+    Symbol { description_sv: "Kraftiga byar av regn och snö", description_en: "Heavy sleet showers", icon: "heavy-sleet-showers", day_night: true },
+    Symbol { description_sv: "Lätta snöbyar", description_en: "Light snow showers", icon: "light-snow-showers", day_night: true },
+    Symbol { description_sv: "Måttliga snöbyar", description_en: "Moderate snow showers", icon: "snow-showers", day_night: true },
+    Symbol { description_sv: "Kraftiga snöbyar", description_en: "Heavy snow showers", icon: "heavy-snow-showers", day_night: true },
+    Symbol { description_sv: "Lätt regn", description_en: "Light rain", icon: "light-rain", day_night: false },
+    Symbol { description_sv: "Måttligt regn", description_en: "Moderate rain", icon: "rain", day_night: false },
+    Symbol { description_sv: "Kraftigt regn", description_en: "Heavy rain", icon: "heavy-rain", day_night: false },
+    Symbol { description_sv: "Åska", description_en: "Thunder", icon: "thunder", day_night: false },
+    Symbol { description_sv: "Lätt snöblandat regn", description_en: "Light sleet", icon: "light-sleet", day_night: false },
+    Symbol { description_sv: "Måttligt snöblandat regn", description_en: "Moderate sleet", icon: "sleet", day_night: false },
+    Symbol { description_sv: "Kraftigt snöblandat regn", description_en: "Heavy sleet", icon: "heavy-sleet", day_night: false },
+    Symbol { description_sv: "Lätt snöfall", description_en: "Light snowfall", icon: "light-snow", day_night: false },
+    Symbol { description_sv: "Måttligt snöfall", description_en: "Moderate snowfall", icon: "snow", day_night: false },
+    Symbol { description_sv: "Kraftigt snöfall", description_en: "Heavy snowfall", icon: "heavy-snow", day_night: false },
+];
+
+/// Human-readable representation of a weather symbol code
+#[derive(Serialize, Clone, Debug)]
+pub struct SymbolInfo {
+    pub code: u8,
+    pub description_sv: &'static str,
+    pub description_en: &'static str,
+    pub icon: String,
+    pub daylight: bool,
+}
+
+/// Returns descriptions and icon name for the given symbol code.
+/// For symbols where the sun may be visible the icon name gets a `-day` or `-night` suffix
+/// depending on the sun elevation at the given time and location.
+///
+/// # Arguments
+///
+/// * 'code' - SMHI Wsymb2 symbol code (1-27)
+/// * 'date_time' - the time the symbol is valid for
+/// * 'lat' - latitude of the location
+/// * 'long' - longitude of the location
+pub fn symbol_info(code: u8, date_time: DateTime<Utc>, lat: f64, long: f64) -> Option<SymbolInfo> {
+    let symbol = SYMBOLS.get((code as usize).checked_sub(1)?)?;
+    let daylight = is_daylight(date_time, lat, long);
+
+    let icon = if symbol.day_night {
+        format!("{}-{}", symbol.icon, if daylight { "day" } else { "night" })
+    } else {
+        symbol.icon.to_string()
+    };
+
+    Some(SymbolInfo {
+        code,
+        description_sv: symbol.description_sv,
+        description_en: symbol.description_en,
+        icon,
+        daylight,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    const KARLSKRONA: (f64, f64) = (56.223306, 15.658389);
+
+    #[test]
+    fn unknown_codes_have_no_symbol() {
+        let noon = utc("2026-06-21T11:00:00Z");
+
+        assert!(symbol_info(0, noon, KARLSKRONA.0, KARLSKRONA.1).is_none());
+        assert!(symbol_info(28, noon, KARLSKRONA.0, KARLSKRONA.1).is_none());
+    }
+
+    #[test]
+    fn looks_up_descriptions_by_code() {
+        let noon = utc("2026-06-21T11:00:00Z");
+
+        let first = symbol_info(1, noon, KARLSKRONA.0, KARLSKRONA.1).unwrap();
+        assert_eq!(first.code, 1);
+        assert_eq!(first.description_sv, "Klart");
+        assert_eq!(first.description_en, "Clear sky");
+
+        let last = symbol_info(27, noon, KARLSKRONA.0, KARLSKRONA.1).unwrap();
+        assert_eq!(last.description_en, "Heavy snowfall");
+        assert_eq!(last.icon, "heavy-snow");
+    }
+
+    #[test]
+    fn icon_follows_daylight_where_the_sun_may_be_visible() {
+        let (lat, long) = KARLSKRONA;
+        let noon = utc("2026-12-21T11:00:00Z");
+        let midnight = utc("2026-12-21T23:00:00Z");
+
+        let day = symbol_info(1, noon, lat, long).unwrap();
+        assert!(day.daylight);
+        assert_eq!(day.icon, "clear-day");
+
+        let night = symbol_info(1, midnight, lat, long).unwrap();
+        assert!(!night.daylight);
+        assert_eq!(night.icon, "clear-night");
+
+        assert_eq!(symbol_info(11, midnight, lat, long).unwrap().icon, "thunder-showers-night");
+        assert_eq!(symbol_info(6, noon, lat, long).unwrap().icon, "overcast");
+        assert_eq!(symbol_info(6, midnight, lat, long).unwrap().icon, "overcast");
+    }
+}
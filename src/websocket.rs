@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use crate::errors::ApiError;
+use crate::events::{EventFilter, EventKind};
+use crate::handlers::{
+    check_source, query_current, query_daily_forecast, query_forecast, query_forecast_at, query_min_max, query_temperature, Reply,
+};
+use crate::negotiation::with_units;
+use crate::units::{Convert, UnitSystem, Units};
+use crate::SharedState;
+
+#[derive(Deserialize, Debug)]
+pub struct WebSocketParams {
+    units: Option<UnitSystem>,
+}
+
+/// Messages sent by clients
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Starts pushing updates of a source, of the given event kinds only if given
+    Subscribe { id: String, events: Option<Vec<EventKind>> },
+    /// Stops pushing updates of a source
+    Unsubscribe { id: String },
+    /// Runs a query, where 'query' is the path of the corresponding endpoint, e.g. 'minmax' or 'forecast/daily',
+    /// and 'params' its query parameters
+    History { request_id: Option<String>, query: String, params: Value },
+}
+
+/// Messages sent to clients
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed { id: String },
+    Unsubscribed { id: String },
+    History { request_id: Option<String>, data: Value },
+    Update { event: &'static str, data: Value },
+    Error { request_id: Option<String>, code: &'static str, message: String },
+}
+
+impl ServerMessage {
+    /// Creates an error message
+    ///
+    /// # Arguments
+    ///
+    /// * 'request_id' - id of the request that failed, if any
+    /// * 'error' - the error
+    fn error(request_id: Option<String>, error: ApiError) -> Self {
+        ServerMessage::Error { request_id, code: error.code(), message: error.into_message() }
+    }
+}
+
+pub async fn websocket(ws: WebSocketUpgrade, params: Result<Query<WebSocketParams>, QueryRejection>, State(state): State<SharedState>) -> Result<Response, ApiError> {
+    let Query(params) = params?;
+    info!("websocket: {:?}", params);
+
+    Ok(ws.on_upgrade(move |socket| serve_socket(socket, state, params.units)))
+}
+
+/// Answers the messages of a client and pushes updates of the sources it subscribes to until it disconnects
+///
+/// # Arguments
+///
+/// * 'socket' - the websocket of the client
+/// * 'state' - shared state
+/// * 'system' - unit system of the updates and of history requests not giving their own
+async fn serve_socket(mut socket: WebSocket, state: SharedState, system: Option<UnitSystem>) {
+    let units = state.config.units(system);
+    let mut events = state.events.subscribe();
+    let mut subscriptions: HashMap<String, EventFilter> = HashMap::new();
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => handle_message(&state, &mut subscriptions, system, text.as_str()).await,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    warn!("websocket error: {}", e);
+                    break;
+                },
+            },
+            event = events.recv() => match event {
+                Ok(mut event) if subscriptions.values().any(|filter| filter.matches(&event)) => {
+                    event.convert(&units);
+                    match with_units(&event, &units) {
+                        Ok(data) => ServerMessage::Update { event: event.name(), data },
+                        Err(e) => ServerMessage::error(None, e),
+                    }
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(count)) => {
+                    warn!("websocket client fell behind, {} events dropped", count);
+                    continue;
+                },
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        let text = match serde_json::to_string(&reply) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("failed to serialize websocket message: {}", e);
+                continue;
+            },
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+
+    info!("websocket closed");
+}
+
+/// Handles a message from a client and returns the reply
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'subscriptions' - sources the client subscribes to
+/// * 'system' - unit system of history requests not giving their own
+/// * 'text' - the message
+async fn handle_message(
+    state: &SharedState,
+    subscriptions: &mut HashMap<String, EventFilter>,
+    system: Option<UnitSystem>,
+    text: &str,
+) -> ServerMessage {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => return ServerMessage::error(None, ApiError::InvalidParameter(format!("invalid message: {}", e))),
+    };
+    info!("websocket: {:?}", message);
+
+    match message {
+        ClientMessage::Subscribe { id, events } => {
+            if let Err(e) = check_source(state, &id).await {
+                return ServerMessage::error(None, e);
+            }
+            subscriptions.insert(id.clone(), EventFilter { sources: Some(vec![id.clone()]), kinds: events });
+
+            ServerMessage::Subscribed { id }
+        },
+        ClientMessage::Unsubscribe { id } => {
+            subscriptions.remove(&id);
+
+            ServerMessage::Unsubscribed { id }
+        },
+        ClientMessage::History { request_id, query, mut params } => {
+            if let (Value::Object(map), Some(system)) = (&mut params, system) {
+                if !map.contains_key("units") {
+                    map.insert("units".to_string(), serde_json::to_value(system).unwrap_or(Value::Null));
+                }
+            }
+
+            match run_query(state, &query, params).await.and_then(|(reply, units)| with_units(&reply, &units)) {
+                Ok(data) => ServerMessage::History { request_id, data },
+                Err(e) => ServerMessage::error(request_id, e),
+            }
+        },
+    }
+}
+
+/// Runs a query the same way as the corresponding endpoint
+///
+/// # Arguments
+///
+/// * 'state' - shared state
+/// * 'query' - path of the endpoint, e.g. 'temperature'
+/// * 'params' - query parameters
+async fn run_query(state: &SharedState, query: &str, params: Value) -> Result<(Reply, Units), ApiError> {
+    match query {
+        "temperature" => query_temperature(state, parse_params(params)?).await,
+        "minmax" => query_min_max(state, parse_params(params)?).await,
+        "current" => query_current(state, parse_params(params)?).await,
+        "forecast" => query_forecast(state, parse_params(params)?).await,
+        "forecast/at" => query_forecast_at(state, parse_params(params)?).await,
+        "forecast/daily" => query_daily_forecast(state, parse_params(params)?).await,
+        query => Err(ApiError::InvalidParameter(format!(
+            "invalid query: {}, use temperature, minmax, current, forecast, forecast/at or forecast/daily", query,
+        ))),
+    }
+}
+
+/// Parses the query parameters of a history request
+///
+/// # Arguments
+///
+/// * 'params' - query parameters as a json object
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, ApiError> {
+    serde_json::from_value(params).map_err(|e| ApiError::InvalidParameter(format!("invalid params: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use serde_json::json;
+    use crate::initialization::Config;
+
+    const CONFIG: &str = r#"
+        [web_server]
+        bind_address = "127.0.0.1"
+        bind_port    = 8081
+
+        [temperature]
+        sensor   = []
+        name     = "east_west"
+        forecast = "smhi"
+
+        [weather_forecast]
+        name = "smhi"
+        lat  = 56.223306
+        long = 15.658389
+
+        [db]
+        db_path         = "{db_path}"
+        max_age_in_days = 30
+
+        [general]
+        log_path      = "/tmp/app.log"
+        log_level     = "Info"
+        log_to_stdout = false
+    "#;
+
+    /// Returns shared state on a new database file in the temporary directory, removed again by `remove`
+    async fn state(name: &str) -> (SharedState, String) {
+        let path = std::env::temp_dir().join(format!("weatherlogger-websocket-{}-{}.db", name, std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        remove(&path);
+
+        let config: Config = toml::from_str(&CONFIG.replace("{db_path}", &path)).unwrap();
+        let db = crate::manager_db::open(&config.db, config.sources()).await.unwrap();
+
+        (SharedState { db, config: Arc::new(config), events: crate::events::channel() }, path)
+    }
+
+    fn remove(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    /// Handles the message and returns the reply as json
+    async fn reply(state: &SharedState, subscriptions: &mut HashMap<String, EventFilter>, system: Option<UnitSystem>, message: Value) -> Value {
+        let reply = handle_message(state, subscriptions, system, &message.to_string()).await;
+
+        serde_json::to_value(reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribes_and_unsubscribes() {
+        let (state, path) = state("subscribe").await;
+        let mut subscriptions = HashMap::new();
+
+        let subscribed = reply(&state, &mut subscriptions, None, json!({"type": "subscribe", "id": "smhi", "events": ["forecast"]})).await;
+        assert_eq!(subscribed, json!({"type": "subscribed", "id": "smhi"}));
+        assert_eq!(subscriptions["smhi"].kinds, Some(vec![EventKind::Forecast]));
+        assert_eq!(subscriptions["smhi"].sources, Some(vec!["smhi".to_string()]));
+
+        reply(&state, &mut subscriptions, None, json!({"type": "subscribe", "id": "east_west"})).await;
+        assert_eq!(subscriptions["east_west"].kinds, None);
+
+        let unsubscribed = reply(&state, &mut subscriptions, None, json!({"type": "unsubscribe", "id": "smhi"})).await;
+        remove(&path);
+
+        assert_eq!(unsubscribed, json!({"type": "unsubscribed", "id": "smhi"}));
+        assert_eq!(subscriptions.keys().collect::<Vec<&String>>(), ["east_west"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_sources_and_messages() {
+        let (state, path) = state("reject").await;
+        let mut subscriptions = HashMap::new();
+
+        let unknown = reply(&state, &mut subscriptions, None, json!({"type": "subscribe", "id": "cabin"})).await;
+        let kind = reply(&state, &mut subscriptions, None, json!({"type": "subscribe", "id": "smhi", "events": ["rain"]})).await;
+        let message = reply(&state, &mut subscriptions, None, json!({"type": "publish", "id": "smhi"})).await;
+        remove(&path);
+
+        assert_eq!(unknown, json!({"type": "error", "request_id": null, "code": "unknown_source", "message": "unknown source 'cabin'"}));
+        assert_eq!((&kind["type"], &kind["code"]), (&json!("error"), &json!("invalid_parameter")));
+        assert_eq!((&message["type"], &message["code"]), (&json!("error"), &json!("invalid_parameter")));
+        assert!(subscriptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_history_requests_with_their_request_id() {
+        let (state, path) = state("history").await;
+        let mut subscriptions = HashMap::new();
+        let system = Some(UnitSystem::Imperial);
+
+        state.db.insert_observation_record("east_west", chrono::Utc::now(), 5.0, None, None).await.unwrap();
+
+        let history = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "request_id": "1", "query": "forecast/daily", "params": {"id": "smhi"},
+        })).await;
+        let imperial = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "request_id": "2", "query": "minmax", "params": {"id": "east_west", "range": "last2d"},
+        })).await;
+        let metric = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "request_id": "3", "query": "minmax", "params": {"id": "east_west", "range": "last2d", "units": "metric"},
+        })).await;
+        let invalid_params = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "request_id": "4", "query": "minmax", "params": {"id": "east_west", "range": "someday"},
+        })).await;
+        let invalid_query = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "request_id": "5", "query": "pressure", "params": {},
+        })).await;
+        let unknown_source = reply(&state, &mut subscriptions, system, json!({
+            "type": "history", "query": "forecast/daily", "params": {"id": "cabin"},
+        })).await;
+        remove(&path);
+
+        assert_eq!(history, json!({"type": "history", "request_id": "1", "data": []}));
+        assert_eq!((&imperial["type"], &imperial["request_id"]), (&json!("history"), &json!("2")));
+        assert_eq!((&imperial["data"]["max"], &imperial["data"]["units"]["temperature"]), (&json!(41.0), &json!("°F")));
+        assert_eq!((&metric["data"]["max"], &metric["data"]["units"]["temperature"]), (&json!(5.0), &json!("°C")));
+        assert_eq!(
+            (&invalid_params["type"], &invalid_params["request_id"], &invalid_params["code"]),
+            (&json!("error"), &json!("4"), &json!("invalid_parameter")),
+        );
+        assert_eq!((&invalid_query["request_id"], &invalid_query["code"]), (&json!("5"), &json!("invalid_parameter")));
+        assert_eq!((&unknown_source["request_id"], &unknown_source["code"]), (&Value::Null, &json!("unknown_source")));
+    }
+}
@@ -0,0 +1,24 @@
+-- Schema and sample data as written by weatherlogger 0.8.x, before schema migrations were introduced
+CREATE TABLE weather (
+    source text not null,
+    datetime integer not null,
+    temperature real null,
+    perceived_temperature real null,
+    humidity integer null,
+    wind_speed real null,
+    lcc_mean integer null,
+    mcc_mean integer null,
+    hcc_mean integer null,
+    symbol_code integer null,
+    constraint primary_key primary key (source, datetime)
+);
+
+INSERT INTO weather (source, datetime, temperature, humidity, perceived_temperature) VALUES
+    ('east_west', 1767225600, -3.0, NULL, -7.4),
+    ('east_west', 1767229380, -4.0, NULL, -8.9),
+    ('east_west', 1767236100, -5.0, NULL, NULL);
+
+INSERT INTO weather (source, datetime, temperature, humidity, wind_speed, lcc_mean, mcc_mean, hcc_mean, symbol_code) VALUES
+    ('smhi', 1767225600, -2.8, 91, 4.2, 8, 3, 0, 6),
+    ('smhi', 1767229200, -3.5, 90, 4.6, 7, 2, 0, 5),
+    ('smhi', 1767232800, -4.1, 88, 5.1, 4, 1, 0, 3);